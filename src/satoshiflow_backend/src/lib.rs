@@ -1,6 +1,8 @@
 use ic_cdk::api::caller;
-use std::collections::HashMap;
-use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::http_request as outcall;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::time::Duration;
+use candid::{CandidType, Nat, Principal};
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
@@ -9,6 +11,37 @@ struct CancelResult {
     fee: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct CreateStreamArgs {
+    recipient: Principal,
+    sats_per_sec: u64,
+    duration_secs: u64,
+    total_locked: u64,
+    title: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum CreateStreamResult {
+    #[serde(rename = "ok")]
+    Ok(u64),
+    #[serde(rename = "err")]
+    Err(String),
+    #[serde(rename = "quota_exceeded")]
+    QuotaExceeded(QuotaError),
+}
+
+// Anti-spam limits per principal, returned as data so clients can react (e.g. wait and retry)
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum QuotaError {
+    CreateRateExceeded { limit: u32, retry_after_secs: u64 },
+    TooManyActiveStreams { limit: u32 },
+    TooManyTemplates { limit: u32 },
+    TooManyMilestones { limit: u32 },
+    AmountBelowMinimum { minimum: u64 },
+}
+
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
 enum ClaimResult {
     #[serde(rename = "ok")]
@@ -57,19 +90,133 @@ struct Stream {
     sender: Principal,
     recipient: Principal,
     sats_per_sec: u64,
+    created_at: u64,
     start_time: u64,
     end_time: u64,
+    duration_secs: u64, // As requested at creation; end_time moves with freezes and rate changes
     total_locked: u64,
     total_released: u64,
     last_release_time: u64,
     buffer: u64,
     status: StreamStatus,
     last_claim_time: u64,
+    total_claimed: u64,
+    total_reclaimed: u64,
+    fee_charged: u64,
+    claim_count: u64,
+    paused_at: Option<u64>,
+    total_paused_secs: u64,
     // New metadata fields
     title: Option<String>,
     description: Option<String>,
     tags: Vec<String>,
     metadata: HashMap<String, String>,
+    public: bool, // Opt-in: anyone may view the stream, e.g. transparent grants
+    recipient_history: Vec<RecipientChange>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct RecipientChange {
+    from: Principal,
+    to: Principal,
+    changed_at: u64,
+    settled: u64, // Buffer paid out to the previous recipient at the time of transfer
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct PendingSenderTransfer {
+    stream_id: u64,
+    from: Principal,
+    to: Principal,
+    proposed_at: u64,
+    expires_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum SenderTransferResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
+}
+
+// Append-only record of ownership changes on a stream
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum AuditAction {
+    RecipientTransferred { from: Principal, to: Principal },
+    SenderTransferProposed { from: Principal, to: Principal },
+    SenderTransferCancelled { from: Principal, to: Principal },
+    SenderTransferred { from: Principal, to: Principal },
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct AuditEntry {
+    timestamp: u64,
+    actor: Principal,
+    action: AuditAction,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum TransferResult {
+    #[serde(rename = "ok")]
+    Ok(u64), // Sats settled to the previous recipient
+    #[serde(rename = "err")]
+    Err(String),
+}
+
+// Stream listing
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+enum StreamSortField {
+    Created,
+    EndTime,
+    Amount,
+    Status,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+enum StreamRole {
+    Sender,
+    Recipient,
+}
+
+// Position after the last stream of a page: its sort key and id
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StreamCursor {
+    key: u64,
+    id: u64,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+struct StreamListOptions {
+    sort_by: Option<StreamSortField>, // Defaults to Created
+    descending: Option<bool>,         // Defaults to true (newest first)
+    role: Option<StreamRole>,         // Defaults to either side
+    cursor: Option<StreamCursor>,
+    limit: Option<u32>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StreamPage {
+    streams: Vec<Stream>,
+    next_cursor: Option<StreamCursor>,
+}
+
+// Partial update of a stream's descriptive fields; None leaves a field untouched
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StreamMetadataUpdate {
+    title: Option<String>,       // An empty string clears the title
+    description: Option<String>, // An empty string clears the description
+    tags: Option<Vec<String>>,   // Replaces the whole tag list
+    set_metadata: Vec<(String, String)>,
+    remove_metadata: Vec<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum MetadataResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
 }
 
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
@@ -90,10 +237,12 @@ enum TemplateResult {
     Ok(u64),
     #[serde(rename = "err")]
     Err(String),
+    #[serde(rename = "quota_exceeded")]
+    QuotaExceeded(QuotaError),
 }
 
 // Notification system
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
 enum NotificationType {
     StreamCreated,
     StreamClaimed,
@@ -102,6 +251,8 @@ enum NotificationType {
     StreamCompleted,
     LowBalance,
     ClaimReminder,
+    RecipientTransferred,
+    SenderTransfer,
 }
 
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
@@ -115,12 +266,207 @@ struct Notification {
     read: bool,
 }
 
-// Statistics and analytics
+// Per-user notification preferences
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct NotificationPreferences {
+    enabled_types: Vec<NotificationType>,
+    low_balance_threshold: Option<u64>, // Notify the sender when unreleased sats drop to this amount
+    claim_reminder_interval_secs: Option<u64>, // Remind the recipient when funds sit unclaimed this long
+    quiet_mode: bool, // Mutes everything without losing the type selection
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            enabled_types: vec![
+                NotificationType::StreamCreated,
+                NotificationType::StreamClaimed,
+                NotificationType::StreamTopUp,
+                NotificationType::StreamCancelled,
+                NotificationType::StreamCompleted,
+                NotificationType::LowBalance,
+                NotificationType::ClaimReminder,
+                NotificationType::RecipientTransferred,
+                NotificationType::SenderTransfer,
+            ],
+            low_balance_threshold: None,
+            claim_reminder_interval_secs: None,
+            quiet_mode: false,
+        }
+    }
+}
+
+impl NotificationPreferences {
+    fn allows(&self, notification_type: &NotificationType) -> bool {
+        !self.quiet_mode && self.enabled_types.contains(notification_type)
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct NotificationPage {
+    notifications: Vec<Notification>,
+    next_cursor: Option<u64>, // Pass back as `cursor` to fetch the next (older) page
+}
+
+// Notification retention
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct RetentionPolicy {
+    max_per_user: u64,
+    max_age_secs: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct NotificationMetrics {
+    stored_notifications: u64,
+    total_evicted: u64,
+    evicted_by_age: u64,
+    evicted_by_cap: u64,
+    prune_runs: u64,
+    last_prune_time: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum RetentionResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum PreferencesResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
+}
+
+// Real-time event feed
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum StreamEventKind {
+    Created,
+    Claimed,
+    ToppedUp,
+    Paused,
+    Resumed,
+    Cancelled,
+    Completed,
+    Reclaimed,
+    RateChanged,
+    RecipientTransferred,
+    SenderTransferred,
+}
+
+// The mutable part of a stream; clients merge this into the record they already hold
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StreamDelta {
+    status: StreamStatus,
+    sats_per_sec: u64,
+    end_time: u64,
+    total_locked: u64,
+    total_released: u64,
+    buffer: u64,
+    last_release_time: u64,
+    last_claim_time: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StreamEvent {
+    seq: u64,
+    stream_id: u64,
+    kind: StreamEventKind,
+    delta: StreamDelta,
+    timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct EventFeedPage {
+    events: Vec<StreamEvent>,
+    latest_seq: u64, // Pass back as `since` on the next poll
+    truncated: bool, // Events after `since` were evicted; reload full streams before applying deltas
+}
+
+#[derive(Clone, Debug, Default)]
+struct UserEventFeed {
+    events: VecDeque<StreamEvent>,
+    evicted_through: u64,
+}
+
+// Webhooks
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct Webhook {
+    id: u64,
+    owner: Principal,
+    url: String,
+    secret: String, // HMAC-SHA256 key, never returned to callers
+    events: Vec<NotificationType>,
+    created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct WebhookInfo {
+    id: u64,
+    url: String,
+    events: Vec<NotificationType>,
+    created_at: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+enum WebhookDeliveryStatus {
+    Pending,
+    InFlight,
+    Delivered,
+    Failed,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct WebhookDelivery {
+    id: u64,
+    webhook_id: u64,
+    stream_id: u64,
+    event: NotificationType,
+    payload: String,
+    status: WebhookDeliveryStatus,
+    attempts: u32,
+    next_attempt_at: u64,
+    in_flight_since: Option<u64>,
+    last_status_code: Option<u64>,
+    last_error: Option<String>,
+    created_at: u64,
+    delivered_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum WebhookResult {
+    #[serde(rename = "ok")]
+    Ok(u64),
+    #[serde(rename = "err")]
+    Err(String),
+}
+
+// Canister HTTP interface
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// Statistics and analytics
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
 struct StreamStats {
     total_streams_created: u64,
     total_volume_locked: u64,
     total_volume_claimed: u64,
+    total_volume_reclaimed: u64,
     active_streams: u64,
     completed_streams: u64,
     cancelled_streams: u64,
@@ -128,6 +474,92 @@ struct StreamStats {
     total_fees_collected: u64,
 }
 
+// Historical analytics
+#[derive(Clone, Debug, PartialEq, Eq, Hash, CandidType, Serialize, Deserialize)]
+enum StatsScope {
+    Global,
+    User(Principal),
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+enum StatsGranularity {
+    Hourly,
+    Daily,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+struct StatsBucket {
+    bucket_start: u64,
+    volume_locked: u64,
+    volume_received: u64, // Locked toward the user as recipient; only recorded for user scopes
+    volume_claimed: u64,
+    streams_created: u64,
+    streams_received: u64,
+    streams_completed: u64,
+    streams_cancelled: u64,
+    fees_collected: u64,
+}
+
+impl StatsBucket {
+    fn merge(&mut self, other: &StatsBucket) {
+        self.volume_locked += other.volume_locked;
+        self.volume_received += other.volume_received;
+        self.volume_claimed += other.volume_claimed;
+        self.streams_created += other.streams_created;
+        self.streams_received += other.streams_received;
+        self.streams_completed += other.streams_completed;
+        self.streams_cancelled += other.streams_cancelled;
+        self.fees_collected += other.fees_collected;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+enum LeaderboardKind {
+    Senders,
+    Recipients,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct LeaderboardEntry {
+    user: Principal,
+    volume: u64,
+    streams: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct CounterpartyStats {
+    counterparty: Principal,
+    sent: u64,
+    received: u64,
+    streams: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StatsConsistency {
+    stored: StreamStats,
+    recomputed: StreamStats,
+    consistent: bool,
+}
+
+// Per-stream analytics returned by get_stream_stats
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StreamAnalytics {
+    stream_id: u64,
+    status: StreamStatus,
+    completion_percent: f64, // Share of total_locked released so far
+    total_locked: u64,
+    total_released: u64,
+    claimed: u64,
+    unclaimed: u64,
+    reclaimed: u64,
+    claim_count: u64,
+    average_claim_interval_secs: Option<u64>,
+    time_paused_secs: u64,
+    projected_end_time: Option<u64>, // None once the stream is cancelled or completed
+    fees_charged: u64,
+    remaining_runway_secs: u64,
+}
+
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
 struct UserStats {
     user: Principal,
@@ -139,635 +571,5035 @@ struct UserStats {
     avg_stream_size: u64,
 }
 
-// Storage for streams
-thread_local! {
-    static STREAMS: std::cell::RefCell<HashMap<u64, Stream>> = std::cell::RefCell::new(HashMap::new());
-    static NEXT_ID: std::cell::RefCell<u64> = std::cell::RefCell::new(0);
+// Canister configuration, set through init arguments and tunable by admins at runtime
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct Limits {
+    max_title_len: u32,
+    max_description_len: u32,
+    max_tags: u32,
+    max_tag_len: u32,
+    max_metadata_entries: u32,
+    max_metadata_key_len: u32,
+    max_metadata_value_len: u32,
+    max_webhooks_per_user: u32,
 }
 
-// Storage for templates
-thread_local! {
-    static TEMPLATES: std::cell::RefCell<HashMap<u64, StreamTemplate>> = std::cell::RefCell::new(HashMap::new());
-    static NEXT_TEMPLATE_ID: std::cell::RefCell<u64> = std::cell::RefCell::new(0);
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct Quotas {
+    creates_per_hour: u32, // Streams a principal may open in any rolling hour
+    max_active_streams: u32, // Active or paused streams a principal may fund at once
+    max_templates: u32,
+    max_milestones_per_stream: u32,
+    min_stream_amount: u64,
 }
 
-// Storage for notifications
-thread_local! {
-    static NOTIFICATIONS: std::cell::RefCell<HashMap<u64, Notification>> = std::cell::RefCell::new(HashMap::new());
-    static NEXT_NOTIFICATION_ID: std::cell::RefCell<u64> = std::cell::RefCell::new(0);
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct CanisterConfig {
+    ledger_canister_id: Option<Principal>,
+    fee_percent: f64, // Fraction of the unused balance kept on cancel, e.g. 0.01 = 1%
+    reclaim_timeout_secs: u64,
+    limits: Limits,
+    quotas: Quotas,
 }
 
-// Storage for stats
-thread_local! {
-    static GLOBAL_STATS: std::cell::RefCell<StreamStats> = std::cell::RefCell::new(StreamStats {
-        total_streams_created: 0,
-        total_volume_locked: 0,
-        total_volume_claimed: 0,
-        active_streams: 0,
-        completed_streams: 0,
-        cancelled_streams: 0,
-        average_stream_duration: 0,
-        total_fees_collected: 0,
-    });
-    static USER_STATS: std::cell::RefCell<HashMap<Principal, UserStats>> = std::cell::RefCell::new(HashMap::new());
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct InitArgs {
+    admins: Vec<Principal>, // Defaults to the installing principal when empty
+    ledger_canister_id: Option<Principal>,
+    fee_percent: Option<f64>,
+    reclaim_timeout_secs: Option<u64>,
+    limits: Option<Limits>,
+    quotas: Option<Quotas>,
 }
 
-const FEE_PERCENT: f64 = 0.01; // 1% fee
-const RECLAIM_TIMEOUT_SECS: u64 = 7 * 24 * 60 * 60; // 7 days
+// Fields left as None keep their current value
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct ConfigUpdate {
+    ledger_canister_id: Option<Principal>,
+    fee_percent: Option<f64>,
+    reclaim_timeout_secs: Option<u64>,
+    limits: Option<Limits>,
+    quotas: Option<Quotas>,
+}
 
-#[ic_cdk::update]
-fn create_stream(
-    recipient: Principal,
-    sats_per_sec: u64,
-    duration_secs: u64,
-    total_locked: u64,
-    title: Option<String>,
-    description: Option<String>,
-    tags: Vec<String>,
-) -> u64 {
-    // Always use the authenticated caller as sender
-    let sender = caller();
-    if sender == Principal::anonymous() {
-        ic_cdk::println!("WARNING: create_stream called by anonymous principal! This stream will not be associated with a user.");
-    }
-    let start_time = ic_cdk::api::time() / 1_000_000_000; // seconds
-    let end_time = start_time + duration_secs;
-    let id = NEXT_ID.with(|id| {
-        let mut id_mut = id.borrow_mut();
-        let curr = *id_mut;
-        *id_mut += 1;
-        curr
-    });
-    let stream = Stream {
-        id,
-        sender,
-        recipient,
-        sats_per_sec,
-        start_time,
-        end_time,
-        total_locked,
-        total_released: 0,
-        last_release_time: start_time,
-        buffer: 0,
-        status: StreamStatus::Active,
-        last_claim_time: start_time,
-        title,
-        description,
-        tags,
-        metadata: HashMap::new(),
-    };
-    ic_cdk::println!("Created stream: id={}, sender={:?}, recipient={:?}", id, sender, recipient);
-    STREAMS.with(|streams| {
-        streams.borrow_mut().insert(id, stream);
-    });
-    update_stats_on_create(sender, total_locked, duration_secs);
-    create_notification(sender, id, NotificationType::StreamCreated, "Stream created successfully".to_string());
-    id
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum ConfigResult {
+    #[serde(rename = "ok")]
+    Ok(CanisterConfig),
+    #[serde(rename = "err")]
+    Err(String),
 }
 
-#[ic_cdk::query]
-fn greet(name: String) -> String {
-    format!("Hello, {}!", name)
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct PendingAdmin {
+    candidate: Principal,
+    proposed_by: Principal,
+    proposed_at: u64,
 }
 
-#[ic_cdk::heartbeat]
-fn canister_heartbeat() {
-    let now = ic_cdk::api::time() / 1_000_000_000; // seconds
-    STREAMS.with(|streams| {
-        let mut streams = streams.borrow_mut();
-        for stream in streams.values_mut() {
-            if stream.status != StreamStatus::Active {
-                continue;
-            }
-            let elapsed = now.saturating_sub(stream.last_release_time);
-            if elapsed == 0 {
-                continue;
-            }
-            let releasable = elapsed * stream.sats_per_sec;
-            let remaining = stream.total_locked.saturating_sub(stream.total_released);
-            let to_release = releasable.min(remaining);
-            stream.total_released += to_release;
-            stream.last_release_time = now;
-            stream.buffer += to_release;
-            
-            // Check milestones
-            check_and_execute_milestones(stream.id, stream.total_released);
-            
-            if stream.total_released >= stream.total_locked || now >= stream.end_time {
-                stream.status = StreamStatus::Completed;
-                create_notification(stream.sender, stream.id, NotificationType::StreamCompleted, "Stream completed".to_string());
-                create_notification(stream.recipient, stream.id, NotificationType::StreamCompleted, "Stream completed".to_string());
-            }
-        }
-    });
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct AdminInfo {
+    admins: Vec<Principal>,
+    pending: Vec<PendingAdmin>,
 }
 
-#[ic_cdk::update]
-fn claim_stream(stream_id: u64) -> ClaimResult {
-    let caller = caller();
-    let now = ic_cdk::api::time() / 1_000_000_000;
-    STREAMS.with(|streams| {
-        let mut streams = streams.borrow_mut();
-        match streams.get_mut(&stream_id) {
-            None => ClaimResult::Err("Stream not found".to_string()),
-            Some(stream) => {
-                if stream.recipient != caller {
-                    return ClaimResult::Err("Only the recipient can claim".to_string());
-                }
-                if stream.buffer == 0 {
-                    return ClaimResult::Err("No funds to claim".to_string());
-                }
-                let claimed = stream.buffer;
-                stream.buffer = 0;
-                stream.last_claim_time = now;
-                ClaimResult::Ok(claimed)
-            }
-        }
-    })
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum AdminResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
 }
 
-#[ic_cdk::update]
-fn top_up_stream(stream_id: u64, additional_sats: u64) -> TopUpResult {
-    let caller = caller();
-    STREAMS.with(|streams| {
-        let mut streams = streams.borrow_mut();
-        match streams.get_mut(&stream_id) {
-            None => TopUpResult::Err("Stream not found".to_string()),
-            Some(stream) => {
-                if stream.sender != caller {
-                    return TopUpResult::Err("Only the sender can top up".to_string());
-                }
-                if stream.status != StreamStatus::Active {
-                    return TopUpResult::Err("Stream is not active".to_string());
-                }
-                stream.total_locked += additional_sats;
-                TopUpResult::Ok(())
-            }
-        }
-    })
+// Circuit breaker: operations an admin can halt independently
+#[derive(Clone, Copy, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+enum HaltableOperation {
+    CreateStream,
+    Claim, // Also covers reclaiming unclaimed funds
+    TopUp,
+    Cancel,
 }
 
-#[ic_cdk::update]
-fn cancel_stream(stream_id: u64) -> CancelStreamResult {
-    let caller = caller();
-    STREAMS.with(|streams| {
-        let mut streams = streams.borrow_mut();
-        match streams.get_mut(&stream_id) {
-            None => CancelStreamResult::Err("Stream not found".to_string()),
-            Some(stream) => {
-                if stream.sender != caller {
-                    return CancelStreamResult::Err("Only the sender can cancel".to_string());
-                }
-                if stream.status != StreamStatus::Active {
-                    return CancelStreamResult::Err("Stream is not active".to_string());
-                }
-                stream.status = StreamStatus::Cancelled;
-                let unused = stream.total_locked.saturating_sub(stream.total_released);
-                let fee = (unused as f64 * FEE_PERCENT).round() as u64;
-                let refund = unused.saturating_sub(fee);
-                CancelStreamResult::Ok(CancelResult { refund, fee })
-            }
-        }
-    })
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct HaltState {
+    halted: Vec<HaltableOperation>,
+    reason: Option<String>,
+    halted_at: Option<u64>,
+    halted_by: Option<Principal>,
+    accrual_frozen_since: Option<u64>, // Set while claims or cancellations are halted
 }
 
-#[ic_cdk::update]
-fn reclaim_unclaimed(stream_id: u64) -> ReclaimResult {
-    let caller = caller();
-    let now = ic_cdk::api::time() / 1_000_000_000;
-    STREAMS.with(|streams| {
-        let mut streams = streams.borrow_mut();
-        match streams.get_mut(&stream_id) {
-            None => ReclaimResult::Err("Stream not found".to_string()),
-            Some(stream) => {
-                if stream.sender != caller {
-                    return ReclaimResult::Err("Only the sender can reclaim".to_string());
-                }
-                if stream.buffer == 0 {
-                    return ReclaimResult::Err("No unclaimed funds to reclaim".to_string());
-                }
-                let claimable_time = stream.end_time.max(stream.last_claim_time) + RECLAIM_TIMEOUT_SECS;
-                if now < claimable_time {
-                    return ReclaimResult::Err("Reclaim timeout not reached".to_string());
-                }
-                let reclaimed = stream.buffer;
-                stream.buffer = 0;
-                ReclaimResult::Ok(reclaimed)
-            }
-        }
-    })
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum HaltResult {
+    #[serde(rename = "ok")]
+    Ok(HaltState),
+    #[serde(rename = "err")]
+    Err(String),
 }
 
-#[ic_cdk::query]
-fn get_stream(stream_id: u64) -> Option<Stream> {
-    STREAMS.with(|streams| streams.borrow().get(&stream_id).cloned())
+// Delegated operators acting for a sender or recipient
+#[derive(Clone, Copy, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+enum DelegatePermission {
+    CreateStream,
+    TopUp,
+    PauseResume,
+    Cancel, // Also covers reclaiming unclaimed funds back to the sender
+    Claim,
+    ChangeRate,
+    UpdateMetadata, // Also covers switching the stream between public and private and recipient annotations
+    ManageOwners,
+    ViewStreams, // Read access to the owner's private streams, stats and counterparties
 }
 
-#[ic_cdk::query]
-fn list_streams_for_user(user: Principal) -> Vec<Stream> {
-    ic_cdk::println!("list_streams_for_user called with principal: {:?}", user);
-    STREAMS.with(|streams| {
-        let map = streams.borrow();
-        for (id, stream) in map.iter() {
-            ic_cdk::println!("Stream {}: sender={:?}, recipient={:?}", id, stream.sender, stream.recipient);
-        }
-        map
-            .values()
-            .filter(|s| s.sender == user || s.recipient == user)
-            .cloned()
-            .collect()
-    })
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct DelegateGrant {
+    owner: Principal,
+    delegate: Principal,
+    permissions: Vec<DelegatePermission>,
+    expires_at: Option<u64>,
+    spend_cap: Option<u64>, // Lifetime limit on sats the delegate may lock through creates and top-ups
+    spent: u64,
+    granted_at: u64,
 }
 
-#[ic_cdk::query]
-fn get_notifications() -> Vec<Notification> {
-    let user = caller();
-    NOTIFICATIONS.with(|notifications| {
-        notifications
-            .borrow()
-            .values()
-            .filter(|n| n.user == user)
-            .cloned()
-            .collect()
-    })
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum DelegateResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
 }
 
-#[ic_cdk::update]
-fn mark_notification_read(notification_id: u64) -> bool {
-    let user = caller();
-    NOTIFICATIONS.with(|notifications| {
-        let mut notifications = notifications.borrow_mut();
-        if let Some(notification) = notifications.get_mut(&notification_id) {
-            if notification.user == user {
-                notification.read = true;
-                return true;
-            }
-        }
-        false
-    })
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum RateChangeResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
 }
 
-fn update_stats_on_create(sender: Principal, total_locked: u64, duration: u64) {
-    GLOBAL_STATS.with(|stats| {
-        let mut stats = stats.borrow_mut();
-        stats.total_streams_created += 1;
-        stats.total_volume_locked += total_locked;
-        stats.active_streams += 1;
-        stats.average_stream_duration = (stats.average_stream_duration + duration) / 2;
-    });
-    
-    USER_STATS.with(|user_stats| {
-        let mut user_stats = user_stats.borrow_mut();
-        let user_stat = user_stats.entry(sender).or_insert(UserStats {
-            user: sender,
-            streams_created: 0,
-            streams_received: 0,
-            total_sent: 0,
-            total_received: 0,
-            total_fees_paid: 0,
-            avg_stream_size: 0,
-        });
-        user_stat.streams_created += 1;
-        user_stat.total_sent += total_locked;
-        user_stat.avg_stream_size = user_stat.total_sent / user_stat.streams_created;
-    });
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum OwnersResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
 }
 
-#[ic_cdk::query]
-fn get_global_stats() -> StreamStats {
-    GLOBAL_STATS.with(|stats| stats.borrow().clone())
+// Multi-signature ownership: sensitive actions need approval from `threshold` of `owners`
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct StreamOwners {
+    owners: Vec<Principal>,
+    threshold: u32,
+    large_top_up_threshold: u64, // Top-ups of at least this many sats need a proposal
 }
 
-#[ic_cdk::query]
-fn get_user_stats(user: Principal) -> Option<UserStats> {
-    USER_STATS.with(|user_stats| user_stats.borrow().get(&user).cloned())
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum ProposalAction {
+    Cancel,
+    ChangeRate(u64),
+    TopUp(u64),
+    UpdateOwners(StreamOwners),
+    TransferSender(Principal), // Starts a sender transfer that the new principal still has to accept
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+enum ProposalStatus {
+    Pending,
+    Executed,
+    Expired,
+    Failed(String), // Approved, but the action could not be applied
 }
 
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-enum PauseResult {
+struct Proposal {
+    id: u64,
+    stream_id: u64,
+    action: ProposalAction,
+    proposer: Principal,
+    approvals: Vec<Principal>,
+    created_at: u64,
+    expires_at: u64,
+    status: ProposalStatus,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum ProposalResult {
     #[serde(rename = "ok")]
-    Ok(()),
+    Ok(Proposal),
     #[serde(rename = "err")]
     Err(String),
 }
 
-#[ic_cdk::update]
-fn pause_stream(stream_id: u64) -> PauseResult {
-    let caller = caller();
-    STREAMS.with(|streams| {
-        let mut streams = streams.borrow_mut();
-        match streams.get_mut(&stream_id) {
-            None => PauseResult::Err("Stream not found".to_string()),
-            Some(stream) => {
-                if stream.sender != caller {
-                    return PauseResult::Err("Only the sender can pause".to_string());
-                }
-                if stream.status != StreamStatus::Active {
-                    return PauseResult::Err("Stream is not active".to_string());
-                }
-                stream.status = StreamStatus::Paused;
-                PauseResult::Ok(())
-            }
-        }
-    })
+// ICRC-7: every stream is an NFT whose id is the stream id and whose owner holds the recipient's rights
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
 }
 
-#[ic_cdk::update]
-fn resume_stream(stream_id: u64) -> PauseResult {
-    let caller = caller();
-    STREAMS.with(|streams| {
-        let mut streams = streams.borrow_mut();
-        match streams.get_mut(&stream_id) {
-            None => PauseResult::Err("Stream not found".to_string()),
-            Some(stream) => {
-                if stream.sender != caller {
-                    return PauseResult::Err("Only the sender can resume".to_string());
-                }
-                if stream.status != StreamStatus::Paused {
-                    return PauseResult::Err("Stream is not paused".to_string());
-                }
-                stream.status = StreamStatus::Active;
-                stream.last_release_time = ic_cdk::api::time() / 1_000_000_000;
-                PauseResult::Ok(())
-            }
-        }
-    })
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(candid::Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+struct Icrc7TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    token_id: Nat,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
 }
 
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-enum MilestoneAction {
-    SendNotification(String),
-    AutoClaim,
-    PauseStream,
-    TopUpStream(u64),
+enum Icrc7TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
 }
 
 #[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-struct Milestone {
-    id: u64,
-    stream_id: u64,
-    trigger_amount: u64, // Amount released to trigger this milestone
-    action: MilestoneAction,
-    triggered: bool,
-    created_by: Principal,
+enum Icrc7TransferResult {
+    Ok(Nat), // Transaction index
+    Err(Icrc7TransferError),
 }
 
-// Storage for milestones
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct SupportedStandard {
+    name: String,
+    url: String,
+}
+
+// Storage for streams
 thread_local! {
-    static MILESTONES: std::cell::RefCell<HashMap<u64, Milestone>> = std::cell::RefCell::new(HashMap::new());
-    static NEXT_MILESTONE_ID: std::cell::RefCell<u64> = std::cell::RefCell::new(0);
+    static STREAMS: std::cell::RefCell<HashMap<u64, Stream>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+    // Ids of the streams each principal is a party to
+    static USER_STREAMS: std::cell::RefCell<HashMap<Principal, BTreeSet<u64>>> = std::cell::RefCell::new(HashMap::new());
+    // Stream ids by normalized (trimmed, lowercase) tag
+    static TAG_INDEX: std::cell::RefCell<HashMap<String, BTreeSet<u64>>> = std::cell::RefCell::new(HashMap::new());
+    // Private key/value notes the recipient keeps on a stream, separate from the sender's metadata
+    static RECIPIENT_ANNOTATIONS: std::cell::RefCell<HashMap<u64, HashMap<String, String>>> = std::cell::RefCell::new(HashMap::new());
 }
 
-#[ic_cdk::update]
-fn add_milestone(stream_id: u64, trigger_amount: u64, action: MilestoneAction) -> u64 {
-    let creator = caller();
-    let id = NEXT_MILESTONE_ID.with(|id| {
-        let mut id_mut = id.borrow_mut();
-        let curr = *id_mut;
-        *id_mut += 1;
-        curr
-    });
-    
-    let milestone = Milestone {
-        id,
-        stream_id,
-        trigger_amount,
-        action,
-        triggered: false,
-        created_by: creator,
-    };
-    
-    MILESTONES.with(|milestones| {
-        milestones.borrow_mut().insert(id, milestone);
-    });
-    
-    id
+// Storage for templates
+thread_local! {
+    static TEMPLATES: std::cell::RefCell<HashMap<u64, StreamTemplate>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_TEMPLATE_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
 }
 
-fn check_and_execute_milestones(stream_id: u64, current_released: u64) {
-    MILESTONES.with(|milestones| {
-        let mut milestones = milestones.borrow_mut();
-        for milestone in milestones.values_mut() {
-            if milestone.stream_id == stream_id 
-                && !milestone.triggered 
-                && current_released >= milestone.trigger_amount {
-                
-                milestone.triggered = true;
-                match &milestone.action {
-                    MilestoneAction::SendNotification(msg) => {
-                        create_notification(
-                            milestone.created_by,
-                            stream_id,
-                            NotificationType::StreamCreated,
-                            msg.clone(),
-                        );
-                    }
-                    MilestoneAction::AutoClaim => {
-                        // Auto-claim logic would go here
-                    }
-                    MilestoneAction::PauseStream => {
-                        // Auto-pause logic would go here
-                    }
-                    MilestoneAction::TopUpStream(_amount) => {
-                        // Auto-topup logic would go here
-                    }
-                }
-            }
-        }
-    });
+// Storage for notifications
+thread_local! {
+    static NOTIFICATIONS: std::cell::RefCell<HashMap<u64, Notification>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_NOTIFICATION_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+    // Per-user index of notification ids; ids are allocated in time order so this is also timestamp order
+    static USER_NOTIFICATIONS: std::cell::RefCell<HashMap<Principal, BTreeSet<u64>>> = std::cell::RefCell::new(HashMap::new());
+    static NOTIFICATION_PREFERENCES: std::cell::RefCell<HashMap<Principal, NotificationPreferences>> = std::cell::RefCell::new(HashMap::new());
+    // Streams that already triggered a LowBalance notification (re-armed on top up)
+    static LOW_BALANCE_NOTIFIED: std::cell::RefCell<HashSet<u64>> = std::cell::RefCell::new(HashSet::new());
+    // Last ClaimReminder sent per stream
+    static LAST_CLAIM_REMINDER: std::cell::RefCell<HashMap<u64, u64>> = std::cell::RefCell::new(HashMap::new());
+    static RETENTION_POLICY: std::cell::RefCell<RetentionPolicy> = const { std::cell::RefCell::new(RetentionPolicy {
+        max_per_user: DEFAULT_MAX_NOTIFICATIONS_PER_USER,
+        max_age_secs: DEFAULT_NOTIFICATION_MAX_AGE_SECS,
+    }) };
+    static NOTIFICATION_METRICS: std::cell::RefCell<NotificationMetrics> = const { std::cell::RefCell::new(NotificationMetrics {
+        stored_notifications: 0,
+        total_evicted: 0,
+        evicted_by_age: 0,
+        evicted_by_cap: 0,
+        prune_runs: 0,
+        last_prune_time: 0,
+    }) };
 }
 
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-struct StreamFilter {
-    status: Option<StreamStatus>,
-    min_amount: Option<u64>,
-    max_amount: Option<u64>,
-    min_duration: Option<u64>,
-    max_duration: Option<u64>,
-    sender: Option<Principal>,
-    recipient: Option<Principal>,
-    created_after: Option<u64>,
-    created_before: Option<u64>,
+// Storage for the event feed
+thread_local! {
+    static EVENT_FEEDS: std::cell::RefCell<HashMap<Principal, UserEventFeed>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_EVENT_SEQ: std::cell::RefCell<u64> = const { std::cell::RefCell::new(1) };
 }
 
-#[ic_cdk::query]
-fn search_streams(filter: StreamFilter) -> Vec<Stream> {
-    let user = caller();
-    STREAMS.with(|streams| {
-        streams
-            .borrow()
-            .values()
-            .filter(|s| {
-                // User must be involved in the stream
-                if s.sender != user && s.recipient != user {
-                    return false;
-                }
-                
-                // Apply filters
-                if let Some(status) = &filter.status {
-                    if s.status != *status {
-                        return false;
-                    }
-                }
-                
-                if let Some(min_amount) = filter.min_amount {
-                    if s.total_locked < min_amount {
-                        return false;
-                    }
-                }
-                
-                if let Some(max_amount) = filter.max_amount {
-                    if s.total_locked > max_amount {
-                        return false;
-                    }
-                }
-                
-                if let Some(min_duration) = filter.min_duration {
-                    if (s.end_time - s.start_time) < min_duration {
-                        return false;
-                    }
-                }
-                
-                if let Some(max_duration) = filter.max_duration {
-                    if (s.end_time - s.start_time) > max_duration {
-                        return false;
-                    }
-                }
-                
-                if let Some(sender) = filter.sender {
-                    if s.sender != sender {
-                        return false;
-                    }
-                }
-                
-                if let Some(recipient) = filter.recipient {
-                    if s.recipient != recipient {
-                        return false;
-                    }
-                }
-                
-                if let Some(created_after) = filter.created_after {
-                    if s.start_time < created_after {
-                        return false;
-                    }
-                }
-                
-                if let Some(created_before) = filter.created_before {
-                    if s.start_time > created_before {
-                        return false;
-                    }
-                }
-                
-                true
-            })
-            .cloned()
-            .collect()
-    })
+// Storage for webhooks
+thread_local! {
+    static WEBHOOKS: std::cell::RefCell<HashMap<u64, Webhook>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_WEBHOOK_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+    static WEBHOOK_DELIVERIES: std::cell::RefCell<HashMap<u64, WebhookDelivery>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_WEBHOOK_DELIVERY_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+    // Bounded delivery log per webhook, oldest first
+    static WEBHOOK_DELIVERY_LOG: std::cell::RefCell<HashMap<u64, VecDeque<u64>>> = std::cell::RefCell::new(HashMap::new());
 }
 
-#[ic_cdk::update]
-fn create_template(name: String, description: String, duration_secs: u64, sats_per_sec: u64) -> TemplateResult {
-    let creator = caller();
-    let now = ic_cdk::api::time() / 1_000_000_000;
-    
-    let id = NEXT_TEMPLATE_ID.with(|id| {
+// Execution counters for periodic jobs, exported on /metrics
+thread_local! {
+    static HEARTBEAT_RUNS: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+    static TIMER_RUNS: std::cell::RefCell<BTreeMap<&'static str, u64>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+// Storage for stats
+thread_local! {
+    static GLOBAL_STATS: std::cell::RefCell<StreamStats> = const { std::cell::RefCell::new(StreamStats {
+        total_streams_created: 0,
+        total_volume_locked: 0,
+        total_volume_claimed: 0,
+        total_volume_reclaimed: 0,
+        active_streams: 0,
+        completed_streams: 0,
+        cancelled_streams: 0,
+        average_stream_duration: 0,
+        total_fees_collected: 0,
+    }) };
+    // Sum of all stream durations, kept so average_stream_duration is a true mean
+    static TOTAL_STREAM_DURATION: std::cell::RefCell<u128> = const { std::cell::RefCell::new(0) };
+    static USER_STATS: std::cell::RefCell<HashMap<Principal, UserStats>> = std::cell::RefCell::new(HashMap::new());
+    // Recent activity is recorded hourly and rolled up into daily buckets once it ages out
+    static HOURLY_BUCKETS: std::cell::RefCell<HashMap<StatsScope, BTreeMap<u64, StatsBucket>>> = std::cell::RefCell::new(HashMap::new());
+    static DAILY_BUCKETS: std::cell::RefCell<HashMap<StatsScope, BTreeMap<u64, StatsBucket>>> = std::cell::RefCell::new(HashMap::new());
+    // Lifetime volume between each pair of principals, keyed by user then counterparty
+    static COUNTERPARTIES: std::cell::RefCell<HashMap<Principal, HashMap<Principal, CounterpartyStats>>> = std::cell::RefCell::new(HashMap::new());
+    // Volume of public streams only, in the hour each stream was created, so the leaderboard never
+    // reveals private activity. Kept hourly: it only grows with the number of public streams.
+    static PUBLIC_VOLUME: std::cell::RefCell<HashMap<StatsScope, BTreeMap<u64, StatsBucket>>> = std::cell::RefCell::new(HashMap::new());
+}
+
+// Storage for runtime configuration and administrators
+thread_local! {
+    static CONFIG: std::cell::RefCell<CanisterConfig> = const { std::cell::RefCell::new(CanisterConfig {
+        ledger_canister_id: None,
+        fee_percent: DEFAULT_FEE_PERCENT,
+        reclaim_timeout_secs: DEFAULT_RECLAIM_TIMEOUT_SECS,
+        limits: DEFAULT_LIMITS,
+        quotas: DEFAULT_QUOTAS,
+    }) };
+    static ADMINS: std::cell::RefCell<BTreeSet<Principal>> = const { std::cell::RefCell::new(BTreeSet::new()) };
+    // Admin handover is two-step: an admin proposes, the candidate accepts
+    static PENDING_ADMINS: std::cell::RefCell<BTreeMap<Principal, PendingAdmin>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+// Storage for the circuit breaker
+thread_local! {
+    static HALT_STATE: std::cell::RefCell<HaltState> = const { std::cell::RefCell::new(HaltState {
+        halted: Vec::new(),
+        reason: None,
+        halted_at: None,
+        halted_by: None,
+        accrual_frozen_since: None,
+    }) };
+}
+
+// Storage for delegate grants
+thread_local! {
+    // Grants keyed by (owner, delegate)
+    static DELEGATE_GRANTS: std::cell::RefCell<HashMap<(Principal, Principal), DelegateGrant>> = std::cell::RefCell::new(HashMap::new());
+}
+
+// Storage for multisig stream ownership
+thread_local! {
+    static STREAM_OWNERS: std::cell::RefCell<HashMap<u64, StreamOwners>> = std::cell::RefCell::new(HashMap::new());
+    static PROPOSALS: std::cell::RefCell<BTreeMap<u64, Proposal>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+    static NEXT_PROPOSAL_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+}
+
+// Storage for ICRC-7 stream tokens
+thread_local! {
+    // Non-default subaccount of a stream token's owner; the owner principal is the stream recipient
+    static TOKEN_SUBACCOUNTS: std::cell::RefCell<HashMap<u64, Vec<u8>>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_TOKEN_TX_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+    // Transfers inside the deduplication window: (caller, arg, transaction index)
+    static RECENT_TOKEN_TRANSFERS: std::cell::RefCell<VecDeque<(Principal, Icrc7TransferArg, u64)>> = const { std::cell::RefCell::new(VecDeque::new()) };
+}
+
+// Storage for sender transfers and the audit log
+thread_local! {
+    // At most one outstanding sender transfer per stream
+    static PENDING_SENDER_TRANSFERS: std::cell::RefCell<HashMap<u64, PendingSenderTransfer>> = std::cell::RefCell::new(HashMap::new());
+    static AUDIT_LOG: std::cell::RefCell<HashMap<u64, Vec<AuditEntry>>> = std::cell::RefCell::new(HashMap::new());
+}
+
+// Storage for quotas
+thread_local! {
+    // Creation times within the last hour, per sender, for the creates-per-hour quota
+    static RECENT_CREATES: std::cell::RefCell<HashMap<Principal, VecDeque<u64>>> = std::cell::RefCell::new(HashMap::new());
+}
+
+const DEFAULT_FEE_PERCENT: f64 = 0.01; // 1% fee
+const MAX_FEE_PERCENT: f64 = 0.1;
+const DEFAULT_RECLAIM_TIMEOUT_SECS: u64 = 7 * 24 * 60 * 60; // 7 days
+const MIN_RECLAIM_TIMEOUT_SECS: u64 = 24 * 60 * 60; // 1 day
+const MAX_RECLAIM_TIMEOUT_SECS: u64 = 365 * 24 * 60 * 60; // 1 year
+const DEFAULT_LIMITS: Limits = Limits {
+    max_title_len: 120,
+    max_description_len: 1000,
+    max_tags: 10,
+    max_tag_len: 32,
+    max_metadata_entries: 20,
+    max_metadata_key_len: 64,
+    max_metadata_value_len: 512,
+    max_webhooks_per_user: 5,
+};
+const DEFAULT_QUOTAS: Quotas = Quotas {
+    creates_per_hour: 20,
+    max_active_streams: 100,
+    max_templates: 20,
+    max_milestones_per_stream: 10,
+    min_stream_amount: 1_000,
+};
+const MAX_INGRESS_ARG_BYTES: usize = 32 * 1024;
+const MAX_STREAM_OWNERS: usize = 10;
+const PROPOSAL_TTL_SECS: u64 = 7 * 24 * 60 * 60; // 7 days
+const SENDER_TRANSFER_TTL_SECS: u64 = 7 * 24 * 60 * 60; // 7 days
+const ICRC7_SYMBOL: &str = "SFLOW";
+const ICRC7_NAME: &str = "SatoshiFlow Streams";
+const ICRC7_DESCRIPTION: &str = "Each token carries the right to receive a SatoshiFlow payment stream";
+const ICRC7_MAX_QUERY_BATCH_SIZE: usize = 100;
+const ICRC7_MAX_UPDATE_BATCH_SIZE: usize = 20;
+const ICRC7_DEFAULT_TAKE: u64 = 100;
+const ICRC7_MAX_TAKE: u64 = 500;
+const ICRC7_MAX_MEMO_SIZE: usize = 32;
+const ICRC7_TX_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours
+const ICRC7_PERMITTED_DRIFT_NANOS: u64 = 2 * 60 * 1_000_000_000; // 2 minutes
+const DEFAULT_STREAM_PAGE_SIZE: u32 = 50;
+const MAX_STREAM_PAGE_SIZE: u32 = 200;
+const MIN_CLAIM_REMINDER_INTERVAL_SECS: u64 = 60 * 60; // 1 hour
+const DEFAULT_NOTIFICATION_PAGE_SIZE: u32 = 50;
+const MAX_NOTIFICATION_PAGE_SIZE: u32 = 200;
+const DEFAULT_MAX_NOTIFICATIONS_PER_USER: u64 = 500;
+const DEFAULT_NOTIFICATION_MAX_AGE_SECS: u64 = 90 * 24 * 60 * 60; // 90 days
+const NOTIFICATION_PRUNE_INTERVAL_SECS: u64 = 60 * 60; // 1 hour
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * HOUR_SECS;
+const HOURLY_BUCKET_RETENTION_SECS: u64 = 7 * DAY_SECS;
+const STATS_DOWNSAMPLE_INTERVAL_SECS: u64 = HOUR_SECS;
+const MAX_TIMESERIES_BUCKETS: usize = 1000;
+const MAX_LEADERBOARD_SIZE: u32 = 100;
+const MAX_EVENTS_PER_USER: usize = 200;
+const DEFAULT_EVENT_PAGE_SIZE: u32 = 100;
+const MAX_WEBHOOK_URL_LEN: usize = 2048;
+const MIN_WEBHOOK_SECRET_LEN: usize = 16;
+const MAX_WEBHOOK_LOG_ENTRIES: usize = 50;
+const MAX_WEBHOOK_ATTEMPTS: u32 = 6;
+const WEBHOOK_BASE_BACKOFF_SECS: u64 = 30; // Doubles after every failed attempt
+const WEBHOOK_IN_FLIGHT_TIMEOUT_SECS: u64 = 10 * 60; // Longer than any outcall can take to resolve
+const WEBHOOK_DISPATCH_INTERVAL_SECS: u64 = 5;
+const WEBHOOK_DISPATCH_BATCH: usize = 10;
+const WEBHOOK_MAX_RESPONSE_BYTES: u64 = 4096;
+const WEBHOOK_OUTCALL_CYCLES: u128 = 400_000_000; // Unused cycles are refunded
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn create_stream(
+    recipient: Principal,
+    sats_per_sec: u64,
+    duration_secs: u64,
+    total_locked: u64,
+    title: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+) -> CreateStreamResult {
+    // Always use the authenticated caller as sender
+    let args = CreateStreamArgs { recipient, sats_per_sec, duration_secs, total_locked, title, description, tags };
+    create_stream_internal(caller(), args)
+}
+
+// Lets a delegate with CreateStream permission open a stream funded by the owner
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn create_stream_as(owner: Principal, args: CreateStreamArgs) -> CreateStreamResult {
+    let caller = caller();
+    let total_locked = args.total_locked;
+    if let Err(e) = authorize(&owner, &caller, DelegatePermission::CreateStream, total_locked) {
+        return CreateStreamResult::Err(e);
+    }
+    let result = create_stream_internal(owner, args);
+    if let CreateStreamResult::Ok(_) = result {
+        charge_delegate(&owner, &caller, total_locked);
+    }
+    result
+}
+
+fn create_stream_internal(sender: Principal, args: CreateStreamArgs) -> CreateStreamResult {
+    let CreateStreamArgs { recipient, sats_per_sec, duration_secs, total_locked, title, description, tags } = args;
+    if let Err(e) = ensure_not_halted(HaltableOperation::CreateStream) {
+        return CreateStreamResult::Err(e);
+    }
+    let metadata = HashMap::new();
+    if let Err(e) = validate_stream_metadata(&title, &description, &tags, &metadata) {
+        return CreateStreamResult::Err(e);
+    }
+    let start_time = ic_cdk::api::time() / 1_000_000_000; // seconds
+    if let Err(e) = check_stream_quotas(sender, total_locked, start_time) {
+        return CreateStreamResult::QuotaExceeded(e);
+    }
+    let end_time = start_time.saturating_add(duration_secs);
+    let id = NEXT_ID.with(|id| {
         let mut id_mut = id.borrow_mut();
         let curr = *id_mut;
         *id_mut += 1;
         curr
     });
-    
-    let template = StreamTemplate {
+    let stream = Stream {
         id,
-        name,
-        description,
-        duration_secs,
+        sender,
+        recipient,
         sats_per_sec,
-        creator,
-        created_at: now,
-        usage_count: 0,
+        created_at: start_time,
+        start_time,
+        end_time,
+        duration_secs,
+        total_locked,
+        total_released: 0,
+        last_release_time: start_time,
+        buffer: 0,
+        status: StreamStatus::Active,
+        last_claim_time: start_time,
+        total_claimed: 0,
+        total_reclaimed: 0,
+        fee_charged: 0,
+        claim_count: 0,
+        paused_at: None,
+        total_paused_secs: 0,
+        title,
+        description,
+        tags,
+        metadata,
+        public: false,
+        recipient_history: Vec::new(),
     };
-    
-    TEMPLATES.with(|templates| {
-        templates.borrow_mut().insert(id, template);
+    ic_cdk::println!("Created stream: id={}, sender={:?}, recipient={:?}", id, sender, recipient);
+    record_stream_event(&stream, StreamEventKind::Created, start_time);
+    index_stream_tags(id, &stream.tags);
+    STREAMS.with(|streams| {
+        streams.borrow_mut().insert(id, stream);
     });
-    
-    TemplateResult::Ok(id)
+    USER_STREAMS.with(|index| {
+        let mut index = index.borrow_mut();
+        index.entry(sender).or_default().insert(id);
+        index.entry(recipient).or_default().insert(id);
+    });
+    update_stats_on_create(sender, recipient, total_locked, duration_secs, start_time);
+    record_recent_create(sender, start_time);
+    create_notification(sender, id, NotificationType::StreamCreated, "Stream created successfully".to_string(), start_time);
+    CreateStreamResult::Ok(id)
 }
 
-#[ic_cdk::update]
-fn create_stream_from_template(template_id: u64, recipient: Principal, total_locked: u64) -> u64 {
-    TEMPLATES.with(|templates| {
-        let mut templates = templates.borrow_mut();
-        if let Some(template) = templates.get_mut(&template_id) {
-            template.usage_count += 1;
-            create_stream(recipient, template.sats_per_sec, template.duration_secs, total_locked, None, None, Vec::new())
-        } else {
-            0 // Handle error properly in real implementation
+// Also drops every principal's expired window so senders who stop creating don't linger
+fn record_recent_create(sender: Principal, now: u64) {
+    RECENT_CREATES.with(|recent| {
+        let mut recent = recent.borrow_mut();
+        recent.retain(|_, times| {
+            while times.front().is_some_and(|t| *t + HOUR_SECS <= now) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        recent.entry(sender).or_default().push_back(now);
+    });
+}
+
+fn check_stream_quotas(sender: Principal, total_locked: u64, now: u64) -> Result<(), QuotaError> {
+    let quotas = config().quotas;
+    if total_locked < quotas.min_stream_amount {
+        return Err(QuotaError::AmountBelowMinimum { minimum: quotas.min_stream_amount });
+    }
+    let ids = USER_STREAMS.with(|index| index.borrow().get(&sender).cloned().unwrap_or_default());
+    let active = STREAMS.with(|streams| {
+        let streams = streams.borrow();
+        ids.iter()
+            .filter_map(|id| streams.get(id))
+            .filter(|s| s.sender == sender && matches!(s.status, StreamStatus::Active | StreamStatus::Paused))
+            .count()
+    });
+    if active >= quotas.max_active_streams as usize {
+        return Err(QuotaError::TooManyActiveStreams { limit: quotas.max_active_streams });
+    }
+    RECENT_CREATES.with(|recent| {
+        let mut recent = recent.borrow_mut();
+        let Some(times) = recent.get_mut(&sender) else {
+            return Ok(());
+        };
+        while times.front().is_some_and(|t| *t + HOUR_SECS <= now) {
+            times.pop_front();
+        }
+        if times.len() >= quotas.creates_per_hour as usize {
+            let retry_after_secs = times.front().map_or(0, |t| (*t + HOUR_SECS).saturating_sub(now));
+            return Err(QuotaError::CreateRateExceeded { limit: quotas.creates_per_hour, retry_after_secs });
+        }
+        if times.is_empty() {
+            recent.remove(&sender);
         }
+        Ok(())
     })
 }
 
 #[ic_cdk::query]
-fn list_templates() -> Vec<StreamTemplate> {
-    TEMPLATES.with(|templates| {
-        templates.borrow().values().cloned().collect()
-    })
+fn greet(name: String) -> String {
+    format!("Hello, {}!", name)
 }
 
-#[ic_cdk::query]
-fn get_stream_stats(stream_id: u64) -> Option<StreamStats> {
-    STREAMS.with(|streams| {
-        if let Some(stream) = streams.borrow().get(&stream_id) {
-            // Calculate basic stats for this specific stream
-            let duration = stream.end_time - stream.start_time;
-            let _completion_rate = if stream.total_locked > 0 {
-                (stream.total_released as f64 / stream.total_locked as f64 * 100.0) as u64
-            } else {
-                0
-            };
-            
-            Some(StreamStats {
-                total_streams_created: 1,
-                total_volume_locked: stream.total_locked,
-                total_volume_claimed: stream.total_released - stream.buffer,
-                active_streams: if stream.status == StreamStatus::Active { 1 } else { 0 },
-                completed_streams: if stream.status == StreamStatus::Completed { 1 } else { 0 },
-                cancelled_streams: if stream.status == StreamStatus::Cancelled { 1 } else { 0 },
-                average_stream_duration: duration,
-                total_fees_collected: 0, // Would need to calculate based on actual fees
-            })
-        } else {
-            None
-        }
-    })
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    apply_init_args(args);
+    start_timers();
 }
 
-fn create_notification(user: Principal, stream_id: u64, notification_type: NotificationType, message: String) {
-    let id = NEXT_NOTIFICATION_ID.with(|id| {
-        let mut id_mut = id.borrow_mut();
-        let curr = *id_mut;
-        *id_mut += 1;
-        curr
+// Canister state lives on the heap and is reset by an upgrade, so the arguments are applied again
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    apply_init_args(args);
+    start_timers();
+}
+
+fn apply_init_args(args: Option<InitArgs>) {
+    let args = args.unwrap_or(InitArgs {
+        admins: Vec::new(),
+        ledger_canister_id: None,
+        fee_percent: None,
+        reclaim_timeout_secs: None,
+        limits: None,
+        quotas: None,
     });
-    
-    let notification = Notification {
-        id,
-        user,
-        stream_id,
-        notification_type,
-        message,
-        timestamp: ic_cdk::api::time() / 1_000_000_000,
-        read: false,
+    let mut admins: BTreeSet<Principal> = args.admins.into_iter().filter(|a| *a != Principal::anonymous()).collect();
+    if admins.is_empty() && caller() != Principal::anonymous() {
+        admins.insert(caller());
+    }
+    ADMINS.with(|current| *current.borrow_mut() = admins);
+    let update = ConfigUpdate {
+        ledger_canister_id: args.ledger_canister_id,
+        fee_percent: args.fee_percent,
+        reclaim_timeout_secs: args.reclaim_timeout_secs,
+        limits: args.limits,
+        quotas: args.quotas,
     };
-    
-    NOTIFICATIONS.with(|notifications| {
-        notifications.borrow_mut().insert(id, notification);
-    });
+    if let Err(e) = apply_config_update(update) {
+        ic_cdk::trap(&format!("Invalid init arguments: {}", e));
+    }
 }
 
-// Export the candid interface
-ic_cdk::export_candid!();
+fn config() -> CanisterConfig {
+    CONFIG.with(|config| config.borrow().clone())
+}
+
+fn apply_config_update(update: ConfigUpdate) -> Result<CanisterConfig, String> {
+    let mut updated = config();
+    if let Some(ledger) = update.ledger_canister_id {
+        updated.ledger_canister_id = Some(ledger);
+    }
+    if let Some(fee) = update.fee_percent {
+        if !(0.0..=MAX_FEE_PERCENT).contains(&fee) {
+            return Err(format!("Fee must be between 0 and {}", MAX_FEE_PERCENT));
+        }
+        updated.fee_percent = fee;
+    }
+    if let Some(timeout) = update.reclaim_timeout_secs {
+        if !(MIN_RECLAIM_TIMEOUT_SECS..=MAX_RECLAIM_TIMEOUT_SECS).contains(&timeout) {
+            return Err(format!(
+                "Reclaim timeout must be between {} and {} seconds",
+                MIN_RECLAIM_TIMEOUT_SECS, MAX_RECLAIM_TIMEOUT_SECS
+            ));
+        }
+        updated.reclaim_timeout_secs = timeout;
+    }
+    if let Some(limits) = update.limits {
+        let values = [
+            limits.max_title_len,
+            limits.max_description_len,
+            limits.max_tags,
+            limits.max_tag_len,
+            limits.max_metadata_entries,
+            limits.max_metadata_key_len,
+            limits.max_metadata_value_len,
+            limits.max_webhooks_per_user,
+        ];
+        if values.contains(&0) {
+            return Err("Limits must be greater than zero".to_string());
+        }
+        updated.limits = limits;
+    }
+    if let Some(quotas) = update.quotas {
+        let values = [quotas.creates_per_hour, quotas.max_active_streams, quotas.max_templates, quotas.max_milestones_per_stream];
+        if values.contains(&0) {
+            return Err("Quotas must be greater than zero".to_string());
+        }
+        updated.quotas = quotas;
+    }
+    CONFIG.with(|config| *config.borrow_mut() = updated.clone());
+    Ok(updated)
+}
+
+#[ic_cdk::query]
+fn get_config() -> CanisterConfig {
+    config()
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn update_config(update: ConfigUpdate) -> ConfigResult {
+    if !is_admin(&caller()) {
+        return ConfigResult::Err("Only an admin can update the configuration".to_string());
+    }
+    match apply_config_update(update) {
+        Ok(config) => ConfigResult::Ok(config),
+        Err(e) => ConfigResult::Err(e),
+    }
+}
+
+#[ic_cdk::query]
+fn get_halt_state() -> HaltState {
+    HALT_STATE.with(|state| state.borrow().clone())
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn halt_operations(operations: Vec<HaltableOperation>, reason: String) -> HaltResult {
+    let caller = caller();
+    if !is_admin(&caller) {
+        return HaltResult::Err("Only an admin can halt operations".to_string());
+    }
+    if operations.is_empty() {
+        return HaltResult::Err("No operations given".to_string());
+    }
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    HALT_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        for operation in operations {
+            if !state.halted.contains(&operation) {
+                state.halted.push(operation);
+            }
+        }
+        state.halted.sort();
+        state.reason = Some(reason);
+        state.halted_at = Some(now);
+        state.halted_by = Some(caller);
+    });
+    sync_accrual_freeze(now);
+    HaltResult::Ok(get_halt_state())
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn resume_operations(operations: Vec<HaltableOperation>) -> HaltResult {
+    if !is_admin(&caller()) {
+        return HaltResult::Err("Only an admin can resume operations".to_string());
+    }
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    HALT_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.halted.retain(|op| !operations.contains(op));
+        if state.halted.is_empty() {
+            state.reason = None;
+            state.halted_at = None;
+            state.halted_by = None;
+        }
+    });
+    sync_accrual_freeze(now);
+    HaltResult::Ok(get_halt_state())
+}
+
+fn ensure_not_halted(operation: HaltableOperation) -> Result<(), String> {
+    HALT_STATE.with(|state| {
+        let state = state.borrow();
+        if !is_halted(&state, operation) {
+            return Ok(());
+        }
+        let reason = state.reason.as_deref().unwrap_or("no reason given");
+        Err(format!("{:?} is halted by an admin: {}", operation, reason))
+    })
+}
+
+// Claims and cancellations both pay out accrued funds, so while accrual is frozen by halting
+// either one, both stay blocked and neither side can take what the frozen time would have moved
+fn is_halted(state: &HaltState, operation: HaltableOperation) -> bool {
+    let settles_accrual = matches!(operation, HaltableOperation::Claim | HaltableOperation::Cancel);
+    state.halted.contains(&operation) || (settles_accrual && state.accrual_frozen_since.is_some())
+}
+
+// Accrual stops while money cannot leave streams, so recipients gain nothing and senders lose
+// nothing during the halt. On unfreeze active streams skip the frozen interval and end later.
+fn sync_accrual_freeze(now: u64) {
+    let frozen_since = HALT_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let should_freeze = state.halted.iter().any(|op| matches!(op, HaltableOperation::Claim | HaltableOperation::Cancel));
+        match (should_freeze, state.accrual_frozen_since) {
+            (true, None) => {
+                state.accrual_frozen_since = Some(now);
+                None
+            }
+            (false, Some(since)) => {
+                state.accrual_frozen_since = None;
+                Some(since)
+            }
+            _ => None,
+        }
+    });
+    let Some(since) = frozen_since else {
+        return;
+    };
+    STREAMS.with(|streams| {
+        for stream in streams.borrow_mut().values_mut() {
+            if stream.status == StreamStatus::Active {
+                thaw_stream(stream, since, now);
+            }
+        }
+    });
+}
+
+// Shifts the stream's clock by the time it actually spent frozen. Streams created, resumed or
+// re-rated during the freeze only lost the time since then; accrual owed from before it is kept.
+fn thaw_stream(stream: &mut Stream, frozen_since: u64, now: u64) {
+    let frozen_from = frozen_since.max(stream.start_time).max(stream.last_release_time);
+    let frozen_secs = now.saturating_sub(frozen_from);
+    stream.last_release_time = stream.last_release_time.saturating_add(frozen_secs);
+    stream.end_time = stream.end_time.saturating_add(frozen_secs);
+}
+
+#[ic_cdk::query]
+fn list_admins() -> AdminInfo {
+    AdminInfo {
+        admins: ADMINS.with(|admins| admins.borrow().iter().copied().collect()),
+        pending: PENDING_ADMINS.with(|pending| pending.borrow().values().cloned().collect()),
+    }
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn propose_admin(candidate: Principal) -> AdminResult {
+    let caller = caller();
+    if !is_admin(&caller) {
+        return AdminResult::Err("Only an admin can propose admins".to_string());
+    }
+    if candidate == Principal::anonymous() {
+        return AdminResult::Err("The anonymous principal cannot be an admin".to_string());
+    }
+    if ADMINS.with(|admins| admins.borrow().contains(&candidate)) {
+        return AdminResult::Err("Principal is already an admin".to_string());
+    }
+    let proposal = PendingAdmin {
+        candidate,
+        proposed_by: caller,
+        proposed_at: ic_cdk::api::time() / 1_000_000_000,
+    };
+    PENDING_ADMINS.with(|pending| pending.borrow_mut().insert(candidate, proposal));
+    AdminResult::Ok(())
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn accept_admin() -> AdminResult {
+    let caller = caller();
+    if PENDING_ADMINS.with(|pending| pending.borrow_mut().remove(&caller)).is_none() {
+        return AdminResult::Err("No pending admin proposal for caller".to_string());
+    }
+    ADMINS.with(|admins| admins.borrow_mut().insert(caller));
+    AdminResult::Ok(())
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn cancel_admin_proposal(candidate: Principal) -> AdminResult {
+    if !is_admin(&caller()) {
+        return AdminResult::Err("Only an admin can cancel admin proposals".to_string());
+    }
+    match PENDING_ADMINS.with(|pending| pending.borrow_mut().remove(&candidate)) {
+        Some(_) => AdminResult::Ok(()),
+        None => AdminResult::Err("No pending proposal for principal".to_string()),
+    }
+}
+
+// Admins may revoke others or step down themselves once a successor has accepted
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn revoke_admin(admin: Principal) -> AdminResult {
+    if !is_admin(&caller()) {
+        return AdminResult::Err("Only an admin can revoke admins".to_string());
+    }
+    ADMINS.with(|admins| {
+        let mut admins = admins.borrow_mut();
+        if !admins.contains(&admin) {
+            return AdminResult::Err("Principal is not an admin".to_string());
+        }
+        if admins.len() == 1 {
+            return AdminResult::Err("Cannot revoke the last admin".to_string());
+        }
+        admins.remove(&admin);
+        AdminResult::Ok(())
+    })
+}
+
+// Guard for every update method: anonymous callers could otherwise own streams anyone can act on
+fn reject_anonymous() -> Result<(), String> {
+    if caller() == Principal::anonymous() {
+        return Err("Anonymous callers are not allowed".to_string());
+    }
+    Ok(())
+}
+
+// Drops anonymous or oversized ingress before execution so it never costs the canister cycles
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    if caller() == Principal::anonymous() {
+        ic_cdk::trap("Anonymous callers are not allowed");
+    }
+    if ic_cdk::api::call::arg_data_raw_size() > MAX_INGRESS_ARG_BYTES {
+        ic_cdk::trap("Request payload too large");
+    }
+    ic_cdk::api::call::accept_message();
+}
+
+// Timers do not survive upgrades, so this runs from both init and post_upgrade
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(NOTIFICATION_PRUNE_INTERVAL_SECS), prune_notifications);
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(STATS_DOWNSAMPLE_INTERVAL_SECS), downsample_stats_buckets);
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(WEBHOOK_DISPATCH_INTERVAL_SECS), || {
+        ic_cdk::spawn(dispatch_webhooks())
+    });
+}
+
+#[ic_cdk::heartbeat]
+fn canister_heartbeat() {
+    HEARTBEAT_RUNS.with(|runs| *runs.borrow_mut() += 1);
+    let now = ic_cdk::api::time() / 1_000_000_000; // seconds
+    // No stream accrues while the circuit breaker freezes time
+    if HALT_STATE.with(|state| state.borrow().accrual_frozen_since.is_some()) {
+        return;
+    }
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        for stream in streams.values_mut() {
+            if stream.status != StreamStatus::Active {
+                continue;
+            }
+            let elapsed = now.saturating_sub(stream.last_release_time);
+            if elapsed == 0 {
+                continue;
+            }
+            let releasable = elapsed * stream.sats_per_sec;
+            let remaining = stream.total_locked.saturating_sub(stream.total_released);
+            let to_release = releasable.min(remaining);
+            stream.total_released += to_release;
+            stream.last_release_time = now;
+            stream.buffer += to_release;
+            
+            // Check milestones
+            check_and_execute_milestones(stream.id, stream.total_released, now);
+            check_balance_notifications(stream, now);
+            
+            if stream.total_released >= stream.total_locked || now >= stream.end_time {
+                stream.status = StreamStatus::Completed;
+                update_stats_on_complete(stream.sender, stream.recipient, now);
+                record_stream_event(stream, StreamEventKind::Completed, now);
+                create_notification(stream.sender, stream.id, NotificationType::StreamCompleted, "Stream completed".to_string(), now);
+                create_notification(stream.recipient, stream.id, NotificationType::StreamCompleted, "Stream completed".to_string(), now);
+            }
+        }
+    });
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn claim_stream(stream_id: u64) -> ClaimResult {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    if let Err(e) = ensure_not_halted(HaltableOperation::Claim) {
+        return ClaimResult::Err(e);
+    }
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        match streams.get_mut(&stream_id) {
+            None => ClaimResult::Err("Stream not found".to_string()),
+            Some(stream) => {
+                // The stream's NFT owner holds the right to claim
+                if let Err(e) = authorize(&token_owner(stream).owner, &caller, DelegatePermission::Claim, 0) {
+                    return ClaimResult::Err(e);
+                }
+                if stream.buffer == 0 {
+                    return ClaimResult::Err("No funds to claim".to_string());
+                }
+                ClaimResult::Ok(settle_buffer(stream, now))
+            }
+        }
+    })
+}
+
+// Pays the whole buffer out to the current recipient
+fn settle_buffer(stream: &mut Stream, now: u64) -> u64 {
+    let claimed = stream.buffer;
+    stream.buffer = 0;
+    stream.last_claim_time = now;
+    stream.total_claimed += claimed;
+    stream.claim_count += 1;
+    update_stats_on_claim(stream.recipient, claimed, now);
+    record_stream_event(stream, StreamEventKind::Claimed, now);
+    create_notification(stream.sender, stream.id, NotificationType::StreamClaimed, format!("Recipient claimed {} sats", claimed), now);
+    claimed
+}
+
+// Hands the stream's future income to another principal. Whatever has already accrued is
+// settled to the current recipient first, so the new recipient only receives what streams from now on.
+// Owner-only: giving away the income cannot be delegated.
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn transfer_stream_recipient(stream_id: u64, new_recipient: Principal) -> TransferResult {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    if new_recipient == Principal::anonymous() {
+        return TransferResult::Err("The anonymous principal cannot receive a stream".to_string());
+    }
+    let result = STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let stream = streams.get_mut(&stream_id).ok_or("Stream not found")?;
+        if stream.recipient != caller {
+            return Err("Only the recipient can transfer the stream".to_string());
+        }
+        reassign_recipient(stream, new_recipient, caller, now)
+    });
+    match result {
+        Ok(settled) => TransferResult::Ok(settled),
+        Err(e) => TransferResult::Err(e),
+    }
+}
+
+fn reassign_recipient(stream: &mut Stream, new_recipient: Principal, actor: Principal, now: u64) -> Result<u64, String> {
+    let old_recipient = stream.recipient;
+    if new_recipient == old_recipient {
+        return Err("Stream already belongs to this recipient".to_string());
+    }
+    if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
+        return Err("Only active or paused streams can be transferred".to_string());
+    }
+    let settled = if stream.buffer > 0 {
+        ensure_not_halted(HaltableOperation::Claim)?;
+        settle_buffer(stream, now)
+    } else {
+        0
+    };
+
+    if stream.public {
+        record_public_volume(stream, false);
+    }
+    stream.recipient = new_recipient;
+    if stream.public {
+        record_public_volume(stream, true);
+    }
+    stream.recipient_history.push(RecipientChange { from: old_recipient, to: new_recipient, changed_at: now, settled });
+    USER_STREAMS.with(|index| {
+        let mut index = index.borrow_mut();
+        if old_recipient != stream.sender {
+            if let Some(ids) = index.get_mut(&old_recipient) {
+                ids.remove(&stream.id);
+            }
+        }
+        index.entry(new_recipient).or_default().insert(stream.id);
+    });
+    // Annotations, reminders and the token subaccount belonged to the previous recipient
+    RECIPIENT_ANNOTATIONS.with(|annotations| annotations.borrow_mut().remove(&stream.id));
+    TOKEN_SUBACCOUNTS.with(|subaccounts| subaccounts.borrow_mut().remove(&stream.id));
+    LAST_CLAIM_REMINDER.with(|reminders| reminders.borrow_mut().remove(&stream.id));
+    // What the previous recipient already claimed stays theirs; the rest now counts toward the new one
+    let unclaimed = stream.total_locked.saturating_sub(stream.total_claimed);
+    move_received_stats(stream.sender, old_recipient, new_recipient, stream.created_at, unclaimed);
+
+    record_stream_event_for(stream, StreamEventKind::RecipientTransferred, &[old_recipient], now);
+    record_audit_entry(stream.id, actor, AuditAction::RecipientTransferred { from: old_recipient, to: new_recipient }, now);
+    let message = format!("Stream recipient changed from {} to {}", old_recipient, new_recipient);
+    notify_parties(&[stream.sender, old_recipient, new_recipient], stream.id, NotificationType::RecipientTransferred, &message, now);
+    Ok(settled)
+}
+
+// Sends one notification to each distinct principal
+fn notify_parties(parties: &[Principal], stream_id: u64, notification_type: NotificationType, message: &str, now: u64) {
+    let parties: BTreeSet<Principal> = parties.iter().copied().collect();
+    for party in parties {
+        create_notification(party, stream_id, notification_type.clone(), message.to_string(), now);
+    }
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn top_up_stream(stream_id: u64, additional_sats: u64) -> TopUpResult {
+    let caller = caller();
+    let sender = match STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.sender)) {
+        None => return TopUpResult::Err("Stream not found".to_string()),
+        Some(sender) => sender,
+    };
+    if let Err(e) = authorize(&sender, &caller, DelegatePermission::TopUp, additional_sats) {
+        return TopUpResult::Err(e);
+    }
+    if let Some(multisig) = stream_owners(stream_id) {
+        if additional_sats >= multisig.large_top_up_threshold {
+            return TopUpResult::Err(format!(
+                "Top-ups of {} sats or more require an owner proposal",
+                multisig.large_top_up_threshold
+            ));
+        }
+    }
+    match execute_top_up(stream_id, additional_sats) {
+        Ok(()) => {
+            charge_delegate(&sender, &caller, additional_sats);
+            TopUpResult::Ok(())
+        }
+        Err(e) => TopUpResult::Err(e),
+    }
+}
+
+// Applies a top-up once the caller has been authorized, directly or through an approved proposal
+fn execute_top_up(stream_id: u64, additional_sats: u64) -> Result<(), String> {
+    ensure_not_halted(HaltableOperation::TopUp)?;
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let stream = streams.get_mut(&stream_id).ok_or("Stream not found")?;
+        if stream.status != StreamStatus::Active {
+            return Err("Stream is not active".to_string());
+        }
+        if stream.public {
+            record_public_volume(stream, false);
+        }
+        stream.total_locked += additional_sats;
+        if stream.public {
+            record_public_volume(stream, true);
+        }
+        update_stats_on_top_up(stream.sender, stream.recipient, additional_sats, now);
+        record_stream_event(stream, StreamEventKind::ToppedUp, now);
+        LOW_BALANCE_NOTIFIED.with(|notified| {
+            notified.borrow_mut().remove(&stream_id);
+        });
+        create_notification(stream.recipient, stream_id, NotificationType::StreamTopUp, format!("Stream topped up with {} sats", additional_sats), now);
+        Ok(())
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn cancel_stream(stream_id: u64) -> CancelStreamResult {
+    let caller = caller();
+    let sender = match STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.sender)) {
+        None => return CancelStreamResult::Err("Stream not found".to_string()),
+        Some(sender) => sender,
+    };
+    if let Err(e) = authorize(&sender, &caller, DelegatePermission::Cancel, 0) {
+        return CancelStreamResult::Err(e);
+    }
+    if stream_owners(stream_id).is_some() {
+        return CancelStreamResult::Err("Cancelling a multisig stream requires an owner proposal".to_string());
+    }
+    match execute_cancel(stream_id) {
+        Ok(result) => CancelStreamResult::Ok(result),
+        Err(e) => CancelStreamResult::Err(e),
+    }
+}
+
+fn execute_cancel(stream_id: u64) -> Result<CancelResult, String> {
+    ensure_not_halted(HaltableOperation::Cancel)?;
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let stream = streams.get_mut(&stream_id).ok_or("Stream not found")?;
+        if stream.status != StreamStatus::Active {
+            return Err("Stream is not active".to_string());
+        }
+        stream.status = StreamStatus::Cancelled;
+        let unused = stream.total_locked.saturating_sub(stream.total_released);
+        let fee = (unused as f64 * config().fee_percent).round() as u64;
+        let refund = unused.saturating_sub(fee);
+        stream.fee_charged = fee;
+        update_stats_on_cancel(stream.sender, stream.recipient, fee, now);
+        record_stream_event(stream, StreamEventKind::Cancelled, now);
+        create_notification(stream.recipient, stream_id, NotificationType::StreamCancelled, "Stream cancelled by sender".to_string(), now);
+        Ok(CancelResult { refund, fee })
+    })
+}
+
+// Changes the streaming rate; the remaining balance then runs out at the new rate
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn change_stream_rate(stream_id: u64, sats_per_sec: u64) -> RateChangeResult {
+    let caller = caller();
+    let sender = match STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.sender)) {
+        None => return RateChangeResult::Err("Stream not found".to_string()),
+        Some(sender) => sender,
+    };
+    if let Err(e) = authorize(&sender, &caller, DelegatePermission::ChangeRate, 0) {
+        return RateChangeResult::Err(e);
+    }
+    if stream_owners(stream_id).is_some() {
+        return RateChangeResult::Err("Changing the rate of a multisig stream requires an owner proposal".to_string());
+    }
+    match execute_rate_change(stream_id, sats_per_sec) {
+        Ok(()) => RateChangeResult::Ok(()),
+        Err(e) => RateChangeResult::Err(e),
+    }
+}
+
+fn execute_rate_change(stream_id: u64, sats_per_sec: u64) -> Result<(), String> {
+    if sats_per_sec == 0 {
+        return Err("Rate must be greater than zero".to_string());
+    }
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let frozen_since = HALT_STATE.with(|state| state.borrow().accrual_frozen_since);
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let stream = streams.get_mut(&stream_id).ok_or("Stream not found")?;
+        if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
+            return Err("Stream is not active".to_string());
+        }
+        let remaining = stream.total_locked.saturating_sub(stream.total_released);
+        stream.sats_per_sec = sats_per_sec;
+        // A paused stream's clock restarts on resume, so its end time is projected from now as well.
+        // While accrual is frozen an active stream's clock stands still, and thawing extends it from there.
+        let clock = match frozen_since {
+            Some(since) if stream.status == StreamStatus::Active => since.max(stream.last_release_time),
+            _ => now,
+        };
+        stream.end_time = clock.saturating_add(remaining.div_ceil(sats_per_sec));
+        record_stream_event(stream, StreamEventKind::RateChanged, now);
+        Ok(())
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn reclaim_unclaimed(stream_id: u64) -> ReclaimResult {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    if let Err(e) = ensure_not_halted(HaltableOperation::Claim) {
+        return ReclaimResult::Err(e);
+    }
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        match streams.get_mut(&stream_id) {
+            None => ReclaimResult::Err("Stream not found".to_string()),
+            Some(stream) => {
+                if let Err(e) = authorize(&stream.sender, &caller, DelegatePermission::Cancel, 0) {
+                    return ReclaimResult::Err(e);
+                }
+                if stream.buffer == 0 {
+                    return ReclaimResult::Err("No unclaimed funds to reclaim".to_string());
+                }
+                if now < reclaimable_at(stream, config().reclaim_timeout_secs) {
+                    return ReclaimResult::Err("Reclaim timeout not reached".to_string());
+                }
+                let reclaimed = stream.buffer;
+                stream.buffer = 0;
+                stream.total_reclaimed += reclaimed;
+                update_stats_on_reclaim(reclaimed);
+                record_stream_event(stream, StreamEventKind::Reclaimed, now);
+                ReclaimResult::Ok(reclaimed)
+            }
+        }
+    })
+}
+
+// Streams with a saturated end time never become reclaimable
+fn reclaimable_at(stream: &Stream, timeout_secs: u64) -> u64 {
+    stream.end_time.max(stream.last_claim_time).saturating_add(timeout_secs)
+}
+
+#[ic_cdk::query]
+fn get_stream(stream_id: u64) -> Option<Stream> {
+    let caller = caller();
+    STREAMS.with(|streams| streams.borrow().get(&stream_id).filter(|s| can_view_stream(s, &caller)).cloned())
+}
+
+// Single place deciding who may read a stream's amounts and counterparties
+fn can_view_stream(stream: &Stream, viewer: &Principal) -> bool {
+    stream.public || can_view_user(&stream.sender, viewer) || can_view_user(&stream.recipient, viewer)
+}
+
+// Per-user data (stats, counterparties, full stream lists) is visible to the user, delegates holding
+// ViewStreams and admins
+fn can_view_user(user: &Principal, viewer: &Principal) -> bool {
+    can_view_user_at(user, viewer, ic_cdk::api::time() / 1_000_000_000)
+}
+
+fn can_view_user_at(user: &Principal, viewer: &Principal, now: u64) -> bool {
+    authorize_at(user, viewer, DelegatePermission::ViewStreams, 0, now).is_ok() || is_admin(viewer)
+}
+
+fn active_grant(owner: &Principal, delegate: &Principal, now: u64) -> Option<DelegateGrant> {
+    DELEGATE_GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .get(&(*owner, *delegate))
+            .filter(|g| g.expires_at.is_none_or(|expires_at| now < expires_at))
+            .cloned()
+    })
+}
+
+// Owner checks go through here so delegates are honoured consistently; only handing the stream
+// itself to someone else stays owner-only. `amount` is the number of sats the action locks,
+// counted against the delegate's spend cap.
+fn authorize(owner: &Principal, caller: &Principal, permission: DelegatePermission, amount: u64) -> Result<(), String> {
+    authorize_at(owner, caller, permission, amount, ic_cdk::api::time() / 1_000_000_000)
+}
+
+fn authorize_at(
+    owner: &Principal,
+    caller: &Principal,
+    permission: DelegatePermission,
+    amount: u64,
+    now: u64,
+) -> Result<(), String> {
+    if owner == caller {
+        return Ok(());
+    }
+    let Some(grant) = active_grant(owner, caller, now) else {
+        return Err("Caller is not the owner or an active delegate".to_string());
+    };
+    if !grant.permissions.contains(&permission) {
+        return Err(format!("Delegate lacks the {:?} permission", permission));
+    }
+    if grant.spend_cap.is_some_and(|cap| grant.spent.saturating_add(amount) > cap) {
+        return Err("Delegate spend cap exceeded".to_string());
+    }
+    Ok(())
+}
+
+fn charge_delegate(owner: &Principal, caller: &Principal, amount: u64) {
+    if owner == caller || amount == 0 {
+        return;
+    }
+    DELEGATE_GRANTS.with(|grants| {
+        if let Some(grant) = grants.borrow_mut().get_mut(&(*owner, *caller)) {
+            grant.spent = grant.spent.saturating_add(amount);
+        }
+    });
+}
+
+// Re-granting replaces permissions, expiry and cap but keeps the amount already spent
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn grant_delegate(
+    delegate: Principal,
+    permissions: Vec<DelegatePermission>,
+    expires_at: Option<u64>,
+    spend_cap: Option<u64>,
+) -> DelegateResult {
+    let owner = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    if delegate == owner || delegate == Principal::anonymous() {
+        return DelegateResult::Err("Invalid delegate".to_string());
+    }
+    if permissions.is_empty() {
+        return DelegateResult::Err("At least one permission is required".to_string());
+    }
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return DelegateResult::Err("Expiry must be in the future".to_string());
+    }
+    DELEGATE_GRANTS.with(|grants| {
+        let mut grants = grants.borrow_mut();
+        let spent = grants.get(&(owner, delegate)).map_or(0, |g| g.spent);
+        grants.insert(
+            (owner, delegate),
+            DelegateGrant { owner, delegate, permissions, expires_at, spend_cap, spent, granted_at: now },
+        );
+    });
+    DelegateResult::Ok(())
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn revoke_delegate(delegate: Principal) -> DelegateResult {
+    let owner = caller();
+    match DELEGATE_GRANTS.with(|grants| grants.borrow_mut().remove(&(owner, delegate))) {
+        Some(_) => DelegateResult::Ok(()),
+        None => DelegateResult::Err("No grant for delegate".to_string()),
+    }
+}
+
+// Grants the caller has given out
+#[ic_cdk::query]
+fn list_my_delegates() -> Vec<DelegateGrant> {
+    let owner = caller();
+    DELEGATE_GRANTS.with(|grants| grants.borrow().values().filter(|g| g.owner == owner).cloned().collect())
+}
+
+// Grants the caller has received
+#[ic_cdk::query]
+fn list_my_delegations() -> Vec<DelegateGrant> {
+    let delegate = caller();
+    DELEGATE_GRANTS.with(|grants| grants.borrow().values().filter(|g| g.delegate == delegate).cloned().collect())
+}
+
+fn stream_owners(stream_id: u64) -> Option<StreamOwners> {
+    STREAM_OWNERS.with(|owners| owners.borrow().get(&stream_id).cloned())
+}
+
+// The sender is always an owner, so every owner set is checked against the current sender
+fn validate_stream_owners(mut owners: StreamOwners, sender: &Principal) -> Result<StreamOwners, String> {
+    owners.owners.sort();
+    owners.owners.dedup();
+    if owners.owners.is_empty() || owners.owners.len() > MAX_STREAM_OWNERS {
+        return Err(format!("A stream needs between 1 and {} owners", MAX_STREAM_OWNERS));
+    }
+    if !owners.owners.contains(sender) {
+        return Err("The stream sender must remain an owner".to_string());
+    }
+    if owners.owners.contains(&Principal::anonymous()) {
+        return Err("The anonymous principal cannot be an owner".to_string());
+    }
+    if owners.threshold == 0 || owners.threshold as usize > owners.owners.len() {
+        return Err("Threshold must be between 1 and the number of owners".to_string());
+    }
+    if owners.large_top_up_threshold == 0 {
+        return Err("Large top-up threshold must be greater than zero".to_string());
+    }
+    Ok(owners)
+}
+
+// One-time switch to multisig; the sender is always an owner. Later changes go through UpdateOwners proposals.
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn configure_stream_owners(stream_id: u64, mut owners: StreamOwners) -> OwnersResult {
+    let caller = caller();
+    let Some(sender) = STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.sender)) else {
+        return OwnersResult::Err("Stream not found".to_string());
+    };
+    if let Err(e) = authorize(&sender, &caller, DelegatePermission::ManageOwners, 0) {
+        return OwnersResult::Err(e);
+    }
+    if stream_owners(stream_id).is_some() {
+        return OwnersResult::Err("Owners are already configured; propose UpdateOwners instead".to_string());
+    }
+    owners.owners.push(sender);
+    match validate_stream_owners(owners, &sender) {
+        Ok(owners) => {
+            STREAM_OWNERS.with(|all| all.borrow_mut().insert(stream_id, owners));
+            OwnersResult::Ok(())
+        }
+        Err(e) => OwnersResult::Err(e),
+    }
+}
+
+#[ic_cdk::query]
+fn get_stream_owners(stream_id: u64) -> Option<StreamOwners> {
+    let caller = caller();
+    let visible = STREAMS.with(|streams| streams.borrow().get(&stream_id).is_some_and(|s| can_view_stream(s, &caller)));
+    if !visible {
+        return None;
+    }
+    stream_owners(stream_id)
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn propose_stream_action(stream_id: u64, action: ProposalAction) -> ProposalResult {
+    let caller = caller();
+    let Some(sender) = STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.sender)) else {
+        return ProposalResult::Err("Stream not found".to_string());
+    };
+    let Some(owners) = stream_owners(stream_id) else {
+        return ProposalResult::Err("Stream has no owner set".to_string());
+    };
+    if !owners.owners.contains(&caller) {
+        return ProposalResult::Err("Only stream owners can propose actions".to_string());
+    }
+    let action = match action {
+        ProposalAction::ChangeRate(0) => return ProposalResult::Err("Rate must be greater than zero".to_string()),
+        ProposalAction::TopUp(0) => return ProposalResult::Err("Top-up amount must be greater than zero".to_string()),
+        ProposalAction::UpdateOwners(updated) => match validate_stream_owners(updated, &sender) {
+            Ok(updated) => ProposalAction::UpdateOwners(updated),
+            Err(e) => return ProposalResult::Err(e),
+        },
+        action => action,
+    };
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let id = NEXT_PROPOSAL_ID.with(|id| {
+        let mut id_mut = id.borrow_mut();
+        let curr = *id_mut;
+        *id_mut += 1;
+        curr
+    });
+    let mut proposal = Proposal {
+        id,
+        stream_id,
+        action,
+        proposer: caller,
+        approvals: vec![caller],
+        created_at: now,
+        expires_at: now + PROPOSAL_TTL_SECS,
+        status: ProposalStatus::Pending,
+    };
+    execute_proposal_if_approved(&mut proposal, &owners);
+    PROPOSALS.with(|proposals| proposals.borrow_mut().insert(id, proposal.clone()));
+    ProposalResult::Ok(proposal)
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn approve_proposal(proposal_id: u64) -> ProposalResult {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let Some(mut proposal) = PROPOSALS.with(|proposals| proposals.borrow().get(&proposal_id).cloned()) else {
+        return ProposalResult::Err("Proposal not found".to_string());
+    };
+    if proposal.status != ProposalStatus::Pending {
+        return ProposalResult::Err("Proposal is no longer pending".to_string());
+    }
+    let Some(owners) = stream_owners(proposal.stream_id) else {
+        return ProposalResult::Err("Stream has no owner set".to_string());
+    };
+    if !owners.owners.contains(&caller) {
+        return ProposalResult::Err("Only stream owners can approve proposals".to_string());
+    }
+    if now >= proposal.expires_at {
+        proposal.status = ProposalStatus::Expired;
+    } else {
+        if proposal.approvals.contains(&caller) {
+            return ProposalResult::Err("Proposal already approved by caller".to_string());
+        }
+        proposal.approvals.push(caller);
+        execute_proposal_if_approved(&mut proposal, &owners);
+    }
+    PROPOSALS.with(|proposals| proposals.borrow_mut().insert(proposal_id, proposal.clone()));
+    match proposal.status {
+        ProposalStatus::Expired => ProposalResult::Err("Proposal has expired".to_string()),
+        _ => ProposalResult::Ok(proposal),
+    }
+}
+
+// Only approvals from current owners count, so removed owners cannot push old proposals through
+// The sender may have changed since the proposal was made, so the owner set is checked again
+fn execute_owners_update(stream_id: u64, updated: StreamOwners) -> Result<(), String> {
+    let sender = STREAMS
+        .with(|streams| streams.borrow().get(&stream_id).map(|s| s.sender))
+        .ok_or("Stream not found")?;
+    let updated = validate_stream_owners(updated, &sender)?;
+    STREAM_OWNERS.with(|all| all.borrow_mut().insert(stream_id, updated));
+    Ok(())
+}
+
+fn execute_proposal_if_approved(proposal: &mut Proposal, owners: &StreamOwners) {
+    let approvals = proposal.approvals.iter().filter(|a| owners.owners.contains(a)).count();
+    if approvals < owners.threshold as usize {
+        return;
+    }
+    let stream_id = proposal.stream_id;
+    let outcome = match &proposal.action {
+        ProposalAction::Cancel => execute_cancel(stream_id).map(|_| ()),
+        ProposalAction::ChangeRate(sats_per_sec) => execute_rate_change(stream_id, *sats_per_sec),
+        ProposalAction::TopUp(amount) => execute_top_up(stream_id, *amount),
+        ProposalAction::UpdateOwners(updated) => execute_owners_update(stream_id, updated.clone()),
+        ProposalAction::TransferSender(new_sender) => start_sender_transfer(stream_id, *new_sender, proposal.proposer),
+    };
+    proposal.status = match outcome {
+        Ok(()) => ProposalStatus::Executed,
+        Err(e) => ProposalStatus::Failed(e),
+    };
+}
+
+#[ic_cdk::query]
+fn get_pending_proposals(stream_id: u64) -> Vec<Proposal> {
+    let caller = caller();
+    let visible = STREAMS.with(|streams| streams.borrow().get(&stream_id).is_some_and(|s| can_view_stream(s, &caller)));
+    if !visible {
+        return Vec::new();
+    }
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    PROPOSALS.with(|proposals| {
+        proposals
+            .borrow()
+            .values()
+            .filter(|p| p.stream_id == stream_id && p.status == ProposalStatus::Pending && now < p.expires_at)
+            .cloned()
+            .collect()
+    })
+}
+
+// Controllers always count as admins so the canister cannot be locked out
+fn is_admin(principal: &Principal) -> bool {
+    ADMINS.with(|admins| admins.borrow().contains(principal)) || ic_cdk::api::is_controller(principal)
+}
+
+#[ic_cdk::query]
+fn list_streams_for_user(user: Principal, options: Option<StreamListOptions>) -> StreamPage {
+    let options = options.unwrap_or_default();
+    // Other callers only see the user's public streams
+    let full_access = can_view_user(&user, &caller());
+    let ids: Vec<u64> = USER_STREAMS.with(|index| {
+        index
+            .borrow()
+            .get(&user)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    });
+    STREAMS.with(|streams| {
+        let streams = streams.borrow();
+        let candidates = ids
+            .iter()
+            .filter_map(|id| streams.get(id))
+            .filter(|s| full_access || s.public)
+            .filter(|s| match options.role {
+                Some(StreamRole::Sender) => s.sender == user,
+                Some(StreamRole::Recipient) => s.recipient == user,
+                None => true,
+            })
+            .collect();
+        paginate_streams(candidates, &options)
+    })
+}
+
+fn stream_sort_key(stream: &Stream, sort_by: &StreamSortField) -> u64 {
+    match sort_by {
+        // Ids are allocated in creation order
+        StreamSortField::Created => stream.id,
+        StreamSortField::EndTime => stream.end_time,
+        StreamSortField::Amount => stream.total_locked,
+        StreamSortField::Status => match stream.status {
+            StreamStatus::Active => 0,
+            StreamStatus::Paused => 1,
+            StreamStatus::Completed => 2,
+            StreamStatus::Cancelled => 3,
+        },
+    }
+}
+
+fn paginate_streams(streams: Vec<&Stream>, options: &StreamListOptions) -> StreamPage {
+    let sort_by = options.sort_by.clone().unwrap_or(StreamSortField::Created);
+    let descending = options.descending.unwrap_or(true);
+    let limit = options
+        .limit
+        .unwrap_or(DEFAULT_STREAM_PAGE_SIZE)
+        .clamp(1, MAX_STREAM_PAGE_SIZE) as usize;
+
+    let mut keyed: Vec<((u64, u64), &Stream)> = streams
+        .into_iter()
+        .map(|s| ((stream_sort_key(s, &sort_by), s.id), s))
+        .filter(|(key, _)| match &options.cursor {
+            None => true,
+            Some(cursor) if descending => *key < (cursor.key, cursor.id),
+            Some(cursor) => *key > (cursor.key, cursor.id),
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    if descending {
+        keyed.reverse();
+    }
+
+    let next_cursor = if keyed.len() > limit {
+        keyed.get(limit - 1).map(|((key, id), _)| StreamCursor { key: *key, id: *id })
+    } else {
+        None
+    };
+    StreamPage {
+        streams: keyed.into_iter().take(limit).map(|(_, s)| s.clone()).collect(),
+        next_cursor,
+    }
+}
+
+#[ic_cdk::query]
+fn get_notifications(cursor: Option<u64>, limit: Option<u32>) -> NotificationPage {
+    let user = caller();
+    let limit = limit
+        .unwrap_or(DEFAULT_NOTIFICATION_PAGE_SIZE)
+        .clamp(1, MAX_NOTIFICATION_PAGE_SIZE) as usize;
+    let ids: Vec<u64> = USER_NOTIFICATIONS.with(|index| {
+        index
+            .borrow()
+            .get(&user)
+            .map(|ids| {
+                // Newest first, strictly older than the cursor
+                ids.range(..cursor.unwrap_or(u64::MAX))
+                    .rev()
+                    .take(limit + 1)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+    let next_cursor = if ids.len() > limit { ids.get(limit - 1).copied() } else { None };
+    let notifications = NOTIFICATIONS.with(|notifications| {
+        let notifications = notifications.borrow();
+        ids.iter()
+            .take(limit)
+            .filter_map(|id| notifications.get(id).cloned())
+            .collect()
+    });
+    NotificationPage { notifications, next_cursor }
+}
+
+#[ic_cdk::query]
+fn unread_count() -> u64 {
+    let user = caller();
+    USER_NOTIFICATIONS.with(|index| {
+        let index = index.borrow();
+        let Some(ids) = index.get(&user) else {
+            return 0;
+        };
+        NOTIFICATIONS.with(|notifications| {
+            let notifications = notifications.borrow();
+            ids.iter()
+                .filter(|id| notifications.get(id).is_some_and(|n| !n.read))
+                .count() as u64
+        })
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn mark_notification_read(notification_id: u64) -> bool {
+    let user = caller();
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        if let Some(notification) = notifications.get_mut(&notification_id) {
+            if notification.user == user {
+                notification.read = true;
+                return true;
+            }
+        }
+        false
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn mark_notifications_read(notification_ids: Vec<u64>) -> u64 {
+    let user = caller();
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let mut marked = 0;
+        for id in notification_ids {
+            if let Some(notification) = notifications.get_mut(&id) {
+                if notification.user == user && !notification.read {
+                    notification.read = true;
+                    marked += 1;
+                }
+            }
+        }
+        marked
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn mark_all_notifications_read() -> u64 {
+    let user = caller();
+    let ids: Vec<u64> = USER_NOTIFICATIONS.with(|index| {
+        index
+            .borrow()
+            .get(&user)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    });
+    mark_notifications_read(ids)
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn delete_notifications(notification_ids: Vec<u64>) -> u64 {
+    let user = caller();
+    let owned: Vec<u64> = NOTIFICATIONS.with(|notifications| {
+        let notifications = notifications.borrow();
+        notification_ids
+            .into_iter()
+            .filter(|id| notifications.get(id).is_some_and(|n| n.user == user))
+            .collect()
+    });
+    owned.iter().filter(|id| remove_notification(**id).is_some()).count() as u64
+}
+
+#[ic_cdk::query]
+fn get_retention_policy() -> RetentionPolicy {
+    RETENTION_POLICY.with(|policy| policy.borrow().clone())
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn set_retention_policy(policy: RetentionPolicy) -> RetentionResult {
+    if !is_admin(&caller()) {
+        return RetentionResult::Err("Only an admin can change the retention policy".to_string());
+    }
+    if policy.max_per_user == 0 || policy.max_age_secs == 0 {
+        return RetentionResult::Err("Retention limits must be greater than zero".to_string());
+    }
+    RETENTION_POLICY.with(|current| *current.borrow_mut() = policy);
+    RetentionResult::Ok(())
+}
+
+#[ic_cdk::query]
+fn get_notification_metrics() -> NotificationMetrics {
+    let mut metrics = NOTIFICATION_METRICS.with(|metrics| metrics.borrow().clone());
+    metrics.stored_notifications = NOTIFICATIONS.with(|notifications| notifications.borrow().len() as u64);
+    metrics
+}
+
+// Enforces the retention policy: anything older than max_age goes first, then users over
+// max_per_user lose their oldest read notifications before any unread ones
+fn prune_notifications() {
+    count_timer_run("notification_prune");
+    prune_notifications_at(ic_cdk::api::time() / 1_000_000_000);
+}
+
+fn prune_notifications_at(now: u64) {
+    let policy = get_retention_policy();
+    let cutoff = now.saturating_sub(policy.max_age_secs);
+    let mut expired = Vec::new();
+    let mut over_cap = Vec::new();
+
+    USER_NOTIFICATIONS.with(|index| {
+        NOTIFICATIONS.with(|notifications| {
+            let notifications = notifications.borrow();
+            for ids in index.borrow().values() {
+                let mut kept_read = Vec::new();
+                let mut kept_unread = Vec::new();
+                for id in ids {
+                    let Some(notification) = notifications.get(id) else {
+                        continue;
+                    };
+                    if notification.timestamp < cutoff {
+                        expired.push(*id);
+                    } else if notification.read {
+                        kept_read.push(*id);
+                    } else {
+                        kept_unread.push(*id);
+                    }
+                }
+                let excess = (kept_read.len() + kept_unread.len()).saturating_sub(policy.max_per_user as usize);
+                // Ids are in time order, so taking from the front evicts the oldest
+                over_cap.extend(kept_read.into_iter().chain(kept_unread).take(excess));
+            }
+        });
+    });
+
+    let evicted_by_age = expired.into_iter().filter(|id| remove_notification(*id).is_some()).count() as u64;
+    let evicted_by_cap = over_cap.into_iter().filter(|id| remove_notification(*id).is_some()).count() as u64;
+
+    NOTIFICATION_METRICS.with(|metrics| {
+        let mut metrics = metrics.borrow_mut();
+        metrics.evicted_by_age += evicted_by_age;
+        metrics.evicted_by_cap += evicted_by_cap;
+        metrics.total_evicted += evicted_by_age + evicted_by_cap;
+        metrics.prune_runs += 1;
+        metrics.last_prune_time = now;
+    });
+}
+
+fn remove_notification(notification_id: u64) -> Option<Notification> {
+    let removed = NOTIFICATIONS.with(|notifications| notifications.borrow_mut().remove(&notification_id))?;
+    USER_NOTIFICATIONS.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(ids) = index.get_mut(&removed.user) {
+            ids.remove(&notification_id);
+            if ids.is_empty() {
+                index.remove(&removed.user);
+            }
+        }
+    });
+    Some(removed)
+}
+
+#[ic_cdk::query]
+fn get_notification_preferences() -> NotificationPreferences {
+    notification_preferences_for(caller())
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn set_notification_preferences(preferences: NotificationPreferences) -> PreferencesResult {
+    let user = caller();
+    if let Some(interval) = preferences.claim_reminder_interval_secs {
+        if interval < MIN_CLAIM_REMINDER_INTERVAL_SECS {
+            return PreferencesResult::Err(format!(
+                "Claim reminder interval must be at least {} seconds",
+                MIN_CLAIM_REMINDER_INTERVAL_SECS
+            ));
+        }
+    }
+    NOTIFICATION_PREFERENCES.with(|prefs| {
+        prefs.borrow_mut().insert(user, preferences);
+    });
+    // Thresholds may have changed, so let low balance alerts fire again
+    LOW_BALANCE_NOTIFIED.with(|notified| {
+        let mut notified = notified.borrow_mut();
+        STREAMS.with(|streams| {
+            for stream in streams.borrow().values().filter(|s| s.sender == user) {
+                notified.remove(&stream.id);
+            }
+        });
+    });
+    PreferencesResult::Ok(())
+}
+
+fn notification_preferences_for(user: Principal) -> NotificationPreferences {
+    NOTIFICATION_PREFERENCES.with(|prefs| prefs.borrow().get(&user).cloned().unwrap_or_default())
+}
+
+// Emits LowBalance / ClaimReminder notifications according to the parties' preferences
+fn check_balance_notifications(stream: &Stream, now: u64) {
+    if let Some(threshold) = notification_preferences_for(stream.sender).low_balance_threshold {
+        let remaining = stream.total_locked.saturating_sub(stream.total_released);
+        let already_notified = LOW_BALANCE_NOTIFIED.with(|notified| notified.borrow().contains(&stream.id));
+        if remaining > 0 && remaining <= threshold && !already_notified {
+            LOW_BALANCE_NOTIFIED.with(|notified| {
+                notified.borrow_mut().insert(stream.id);
+            });
+            create_notification(
+                stream.sender,
+                stream.id,
+                NotificationType::LowBalance,
+                format!("Stream balance is low: {} sats remaining", remaining),
+                now,
+            );
+        }
+    }
+
+    if let Some(interval) = notification_preferences_for(stream.recipient).claim_reminder_interval_secs {
+        if stream.buffer == 0 {
+            return;
+        }
+        let last_reminder = LAST_CLAIM_REMINDER.with(|reminders| reminders.borrow().get(&stream.id).copied().unwrap_or(0));
+        if now.saturating_sub(stream.last_claim_time.max(last_reminder)) >= interval {
+            LAST_CLAIM_REMINDER.with(|reminders| {
+                reminders.borrow_mut().insert(stream.id, now);
+            });
+            create_notification(
+                stream.recipient,
+                stream.id,
+                NotificationType::ClaimReminder,
+                format!("You have {} sats ready to claim", stream.buffer),
+                now,
+            );
+        }
+    }
+}
+
+fn update_stats_on_create(sender: Principal, recipient: Principal, total_locked: u64, duration: u64, now: u64) {
+    GLOBAL_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.total_streams_created += 1;
+        stats.total_volume_locked += total_locked;
+        stats.active_streams += 1;
+        let total_duration = TOTAL_STREAM_DURATION.with(|total| {
+            let mut total = total.borrow_mut();
+            *total += duration as u128;
+            *total
+        });
+        stats.average_stream_duration = (total_duration / stats.total_streams_created as u128) as u64;
+    });
+    record_bucket(&[StatsScope::Global, StatsScope::User(sender)], now, |bucket| {
+        bucket.volume_locked += total_locked;
+        bucket.streams_created += 1;
+    });
+    record_bucket(&[StatsScope::User(recipient)], now, |bucket| {
+        bucket.volume_received += total_locked;
+        bucket.streams_received += 1;
+    });
+    update_counterparty_volume(sender, recipient, total_locked, true);
+    
+    with_user_stats(sender, |user_stat| {
+        user_stat.streams_created += 1;
+        user_stat.total_sent += total_locked;
+        user_stat.avg_stream_size = user_stat.total_sent / user_stat.streams_created;
+    });
+    with_user_stats(recipient, |user_stat| user_stat.streams_received += 1);
+}
+
+fn empty_user_stats(user: Principal) -> UserStats {
+    UserStats {
+        user,
+        streams_created: 0,
+        streams_received: 0,
+        total_sent: 0,
+        total_received: 0,
+        total_fees_paid: 0,
+        avg_stream_size: 0,
+    }
+}
+
+fn with_user_stats(user: Principal, f: impl FnOnce(&mut UserStats)) {
+    USER_STATS.with(|user_stats| {
+        let mut user_stats = user_stats.borrow_mut();
+        f(user_stats.entry(user).or_insert_with(|| empty_user_stats(user)));
+    });
+}
+
+fn update_stats_on_complete(sender: Principal, recipient: Principal, now: u64) {
+    GLOBAL_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.active_streams = stats.active_streams.saturating_sub(1);
+        stats.completed_streams += 1;
+    });
+    record_bucket(&stream_scopes(sender, recipient), now, |bucket| bucket.streams_completed += 1);
+}
+
+fn update_stats_on_cancel(sender: Principal, recipient: Principal, fee: u64, now: u64) {
+    GLOBAL_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.active_streams = stats.active_streams.saturating_sub(1);
+        stats.cancelled_streams += 1;
+        stats.total_fees_collected += fee;
+    });
+    record_bucket(&stream_scopes(sender, recipient), now, |bucket| bucket.streams_cancelled += 1);
+    record_bucket(&[StatsScope::Global, StatsScope::User(sender)], now, |bucket| bucket.fees_collected += fee);
+    with_user_stats(sender, |user_stat| user_stat.total_fees_paid += fee);
+}
+
+fn update_stats_on_claim(recipient: Principal, amount: u64, now: u64) {
+    GLOBAL_STATS.with(|stats| stats.borrow_mut().total_volume_claimed += amount);
+    record_bucket(&[StatsScope::Global, StatsScope::User(recipient)], now, |bucket| bucket.volume_claimed += amount);
+    with_user_stats(recipient, |user_stat| user_stat.total_received += amount);
+}
+
+fn update_stats_on_top_up(sender: Principal, recipient: Principal, amount: u64, now: u64) {
+    GLOBAL_STATS.with(|stats| stats.borrow_mut().total_volume_locked += amount);
+    record_bucket(&[StatsScope::Global, StatsScope::User(sender)], now, |bucket| bucket.volume_locked += amount);
+    record_bucket(&[StatsScope::User(recipient)], now, |bucket| bucket.volume_received += amount);
+    update_counterparty_volume(sender, recipient, amount, false);
+    with_user_stats(sender, |user_stat| {
+        user_stat.total_sent += amount;
+        user_stat.avg_stream_size = user_stat.total_sent / user_stat.streams_created.max(1);
+    });
+}
+
+fn update_stats_on_reclaim(amount: u64) {
+    GLOBAL_STATS.with(|stats| stats.borrow_mut().total_volume_reclaimed += amount);
+}
+
+fn update_stats_on_pause() {
+    GLOBAL_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.active_streams = stats.active_streams.saturating_sub(1);
+    });
+}
+
+fn update_stats_on_resume() {
+    GLOBAL_STATS.with(|stats| stats.borrow_mut().active_streams += 1);
+}
+
+fn stream_scopes(sender: Principal, recipient: Principal) -> Vec<StatsScope> {
+    let mut scopes = vec![StatsScope::Global, StatsScope::User(sender)];
+    if recipient != sender {
+        scopes.push(StatsScope::User(recipient));
+    }
+    scopes
+}
+
+fn record_bucket(scopes: &[StatsScope], now: u64, f: impl Fn(&mut StatsBucket)) {
+    let bucket_start = now - now % HOUR_SECS;
+    HOURLY_BUCKETS.with(|buckets| {
+        let mut buckets = buckets.borrow_mut();
+        for scope in scopes {
+            let bucket = buckets
+                .entry(scope.clone())
+                .or_default()
+                .entry(bucket_start)
+                .or_insert_with(|| StatsBucket { bucket_start, ..Default::default() });
+            f(bucket);
+        }
+    });
+}
+
+// Folds hourly buckets older than the retention window into their daily bucket
+fn downsample_stats_buckets() {
+    count_timer_run("stats_downsample");
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let cutoff = now.saturating_sub(HOURLY_BUCKET_RETENTION_SECS);
+    let cutoff = cutoff - cutoff % DAY_SECS; // Only roll up whole days
+    HOURLY_BUCKETS.with(|hourly| {
+        DAILY_BUCKETS.with(|daily| {
+            let mut hourly = hourly.borrow_mut();
+            let mut daily = daily.borrow_mut();
+            for (scope, buckets) in hourly.iter_mut() {
+                let recent = buckets.split_off(&cutoff);
+                let expired = std::mem::replace(buckets, recent);
+                if expired.is_empty() {
+                    continue;
+                }
+                let days = daily.entry(scope.clone()).or_default();
+                for bucket in expired.values() {
+                    let day_start = bucket.bucket_start - bucket.bucket_start % DAY_SECS;
+                    days.entry(day_start)
+                        .or_insert_with(|| StatsBucket { bucket_start: day_start, ..Default::default() })
+                        .merge(bucket);
+                }
+            }
+            hourly.retain(|_, buckets| !buckets.is_empty());
+        });
+    });
+}
+
+// `from` is widened to the start of its hour or day: buckets are only ever returned whole
+#[ic_cdk::query]
+fn get_stats_timeseries(scope: StatsScope, granularity: StatsGranularity, from: u64, to: u64) -> Vec<StatsBucket> {
+    if from >= to {
+        return Vec::new();
+    }
+    if let StatsScope::User(user) = scope {
+        if !can_view_user(&user, &caller()) {
+            return Vec::new();
+        }
+    }
+    let from = match granularity {
+        StatsGranularity::Hourly => from - from % HOUR_SECS,
+        StatsGranularity::Daily => from - from % DAY_SECS,
+    };
+    let hourly: Vec<StatsBucket> = HOURLY_BUCKETS.with(|buckets| {
+        buckets
+            .borrow()
+            .get(&scope)
+            .map(|b| b.range(from..to).map(|(_, bucket)| bucket.clone()).collect())
+            .unwrap_or_default()
+    });
+    if granularity == StatsGranularity::Hourly {
+        return hourly.into_iter().take(MAX_TIMESERIES_BUCKETS).collect();
+    }
+
+    // Daily view: rolled-up days plus the hourly buckets that have not been downsampled yet
+    let mut days: BTreeMap<u64, StatsBucket> = DAILY_BUCKETS.with(|buckets| {
+        buckets
+            .borrow()
+            .get(&scope)
+            .map(|b| b.range(from..to).map(|(k, bucket)| (*k, bucket.clone())).collect())
+            .unwrap_or_default()
+    });
+    for bucket in &hourly {
+        let day_start = bucket.bucket_start - bucket.bucket_start % DAY_SECS;
+        days.entry(day_start)
+            .or_insert_with(|| StatsBucket { bucket_start: day_start, ..Default::default() })
+            .merge(bucket);
+    }
+    days.into_values().take(MAX_TIMESERIES_BUCKETS).collect()
+}
+
+// Re-attributes one stream's received volume, in the bucket it was created in, when its recipient changes
+fn move_received_stats(sender: Principal, from: Principal, to: Principal, created_at: u64, volume: u64) {
+    with_user_stats(from, |user_stat| user_stat.streams_received = user_stat.streams_received.saturating_sub(1));
+    with_user_stats(to, |user_stat| user_stat.streams_received += 1);
+
+    COUNTERPARTIES.with(|counterparties| {
+        let mut counterparties = counterparties.borrow_mut();
+        let mut shrink = |owner: Principal, counterparty: Principal, outgoing: bool| {
+            let Some(entries) = counterparties.get_mut(&owner) else {
+                return;
+            };
+            if let Some(entry) = entries.get_mut(&counterparty) {
+                if outgoing {
+                    entry.sent = entry.sent.saturating_sub(volume);
+                } else {
+                    entry.received = entry.received.saturating_sub(volume);
+                }
+                entry.streams = entry.streams.saturating_sub(1);
+                if entry.streams == 0 && entry.sent == 0 && entry.received == 0 {
+                    entries.remove(&counterparty);
+                }
+            }
+            if entries.is_empty() {
+                counterparties.remove(&owner);
+            }
+        };
+        shrink(sender, from, true);
+        if sender != from {
+            shrink(from, sender, false);
+        }
+    });
+    update_counterparty_volume(sender, to, volume, true);
+
+    let hour_start = created_at - created_at % HOUR_SECS;
+    let day_start = created_at - created_at % DAY_SECS;
+    let from_scope = StatsScope::User(from);
+    let to_scope = StatsScope::User(to);
+    // The creation hour may already have been rolled up into its day
+    let still_hourly = HOURLY_BUCKETS.with(|buckets| {
+        buckets.borrow().get(&from_scope).is_some_and(|b| b.contains_key(&hour_start))
+    });
+    let (storage, bucket_start) = if still_hourly { (&HOURLY_BUCKETS, hour_start) } else { (&DAILY_BUCKETS, day_start) };
+    storage.with(|buckets| {
+        let mut buckets = buckets.borrow_mut();
+        if let Some(bucket) = buckets.get_mut(&from_scope).and_then(|b| b.get_mut(&bucket_start)) {
+            bucket.volume_received = bucket.volume_received.saturating_sub(volume);
+            bucket.streams_received = bucket.streams_received.saturating_sub(1);
+        }
+        let bucket = buckets
+            .entry(to_scope)
+            .or_default()
+            .entry(bucket_start)
+            .or_insert_with(|| StatsBucket { bucket_start, ..Default::default() });
+        bucket.volume_received += volume;
+        bucket.streams_received += 1;
+    });
+}
+
+fn update_counterparty_volume(sender: Principal, recipient: Principal, amount: u64, new_stream: bool) {
+    COUNTERPARTIES.with(|counterparties| {
+        let mut counterparties = counterparties.borrow_mut();
+        let outgoing = counterparties
+            .entry(sender)
+            .or_default()
+            .entry(recipient)
+            .or_insert_with(|| CounterpartyStats { counterparty: recipient, sent: 0, received: 0, streams: 0 });
+        outgoing.sent += amount;
+        if new_stream {
+            outgoing.streams += 1;
+        }
+        if sender == recipient {
+            return;
+        }
+        let incoming = counterparties
+            .entry(recipient)
+            .or_default()
+            .entry(sender)
+            .or_insert_with(|| CounterpartyStats { counterparty: sender, sent: 0, received: 0, streams: 0 });
+        incoming.received += amount;
+        if new_stream {
+            incoming.streams += 1;
+        }
+    });
+}
+
+// Adds or removes a public stream's contribution to the public leaderboard aggregate. Callers
+// remove it before changing the stream's amount or parties and add it back afterwards.
+fn record_public_volume(stream: &Stream, add: bool) {
+    let bucket_start = stream.created_at - stream.created_at % HOUR_SECS;
+    let apply = |value: &mut u64, amount: u64| {
+        *value = if add { value.saturating_add(amount) } else { value.saturating_sub(amount) };
+    };
+    PUBLIC_VOLUME.with(|volume| {
+        let mut volume = volume.borrow_mut();
+        for (user, as_sender) in [(stream.sender, true), (stream.recipient, false)] {
+            let scope = StatsScope::User(user);
+            let buckets = volume.entry(scope.clone()).or_default();
+            let bucket = buckets
+                .entry(bucket_start)
+                .or_insert_with(|| StatsBucket { bucket_start, ..Default::default() });
+            if as_sender {
+                apply(&mut bucket.volume_locked, stream.total_locked);
+                apply(&mut bucket.streams_created, 1);
+            } else {
+                apply(&mut bucket.volume_received, stream.total_locked);
+                apply(&mut bucket.streams_received, 1);
+            }
+            if bucket.streams_created == 0 && bucket.streams_received == 0 {
+                buckets.remove(&bucket_start);
+            }
+            if buckets.is_empty() {
+                volume.remove(&scope);
+            }
+        }
+    });
+}
+
+// Ranks users by volume within [from, to). Admins rank from the full per-user stats buckets, where
+// ranges older than the hourly retention window resolve to whole days: a rolled-up day counts in
+// full once it overlaps the range. Everyone else ranks from public streams only, each counted in
+// the hour it was created, so `from` is widened to the start of its hour.
+#[ic_cdk::query]
+fn get_leaderboard(kind: LeaderboardKind, limit: u32, from: u64, to: u64) -> Vec<LeaderboardEntry> {
+    if from >= to {
+        return Vec::new();
+    }
+    if !is_admin(&caller()) {
+        return public_leaderboard(kind, limit, from, to);
+    }
+    let mut totals = HashMap::new();
+    HOURLY_BUCKETS.with(|buckets| add_leaderboard_volume(&mut totals, &kind, &buckets.borrow(), from..to));
+    let day_from = from - from % DAY_SECS;
+    DAILY_BUCKETS.with(|buckets| add_leaderboard_volume(&mut totals, &kind, &buckets.borrow(), day_from..to));
+    rank_leaderboard(totals, limit)
+}
+
+fn public_leaderboard(kind: LeaderboardKind, limit: u32, from: u64, to: u64) -> Vec<LeaderboardEntry> {
+    let mut totals = HashMap::new();
+    PUBLIC_VOLUME.with(|volume| add_leaderboard_volume(&mut totals, &kind, &volume.borrow(), from..to));
+    rank_leaderboard(totals, limit)
+}
+
+fn add_leaderboard_volume(
+    totals: &mut HashMap<Principal, LeaderboardEntry>,
+    kind: &LeaderboardKind,
+    buckets: &HashMap<StatsScope, BTreeMap<u64, StatsBucket>>,
+    range: std::ops::Range<u64>,
+) {
+    for (scope, buckets) in buckets {
+        let StatsScope::User(user) = scope else {
+            continue;
+        };
+        for bucket in buckets.range(range.clone()).map(|(_, b)| b) {
+            let (volume, streams) = match kind {
+                LeaderboardKind::Senders => (bucket.volume_locked, bucket.streams_created),
+                LeaderboardKind::Recipients => (bucket.volume_received, bucket.streams_received),
+            };
+            if volume == 0 {
+                continue;
+            }
+            let entry = totals
+                .entry(*user)
+                .or_insert_with(|| LeaderboardEntry { user: *user, volume: 0, streams: 0 });
+            entry.volume = entry.volume.saturating_add(volume);
+            entry.streams += streams;
+        }
+    }
+}
+
+fn rank_leaderboard(totals: HashMap<Principal, LeaderboardEntry>, limit: u32) -> Vec<LeaderboardEntry> {
+    let mut entries: Vec<LeaderboardEntry> = totals.into_values().collect();
+    entries.sort_by(|a, b| b.volume.cmp(&a.volume).then(a.user.cmp(&b.user)));
+    entries.truncate(limit.min(MAX_LEADERBOARD_SIZE) as usize);
+    entries
+}
+
+#[ic_cdk::query]
+fn get_top_counterparties(user: Principal, limit: u32) -> Vec<CounterpartyStats> {
+    if !can_view_user(&user, &caller()) {
+        return Vec::new();
+    }
+    let mut entries: Vec<CounterpartyStats> = COUNTERPARTIES.with(|counterparties| {
+        counterparties
+            .borrow()
+            .get(&user)
+            .map(|c| c.values().cloned().collect())
+            .unwrap_or_default()
+    });
+    entries.sort_by(|a, b| {
+        let volume = |c: &CounterpartyStats| c.sent.saturating_add(c.received);
+        volume(b).cmp(&volume(a)).then(a.counterparty.cmp(&b.counterparty))
+    });
+    entries.truncate(limit.min(MAX_LEADERBOARD_SIZE) as usize);
+    entries
+}
+
+fn recompute_stats_from_streams() -> StreamStats {
+    STREAMS.with(|streams| {
+        let streams = streams.borrow();
+        let mut stats = StreamStats {
+            total_streams_created: streams.len() as u64,
+            total_volume_locked: 0,
+            total_volume_claimed: 0,
+            total_volume_reclaimed: 0,
+            active_streams: 0,
+            completed_streams: 0,
+            cancelled_streams: 0,
+            average_stream_duration: 0,
+            total_fees_collected: 0,
+        };
+        let mut total_duration: u128 = 0;
+        for stream in streams.values() {
+            stats.total_volume_locked += stream.total_locked;
+            stats.total_volume_claimed += stream.total_claimed;
+            stats.total_volume_reclaimed += stream.total_reclaimed;
+            stats.total_fees_collected += stream.fee_charged;
+            total_duration += stream.duration_secs as u128;
+            match stream.status {
+                StreamStatus::Active => stats.active_streams += 1,
+                StreamStatus::Completed => stats.completed_streams += 1,
+                StreamStatus::Cancelled => stats.cancelled_streams += 1,
+                StreamStatus::Paused => {}
+            }
+        }
+        if stats.total_streams_created > 0 {
+            stats.average_stream_duration = (total_duration / stats.total_streams_created as u128) as u64;
+        }
+        stats
+    })
+}
+
+// Debug aid: rebuilds the global stats from STREAMS and compares them with the running counters
+#[ic_cdk::query]
+fn debug_recompute_stats() -> Option<StatsConsistency> {
+    if !is_admin(&caller()) {
+        return None;
+    }
+    let stored = get_global_stats();
+    let recomputed = recompute_stats_from_streams();
+    let consistent = stored == recomputed;
+    Some(StatsConsistency { stored, recomputed, consistent })
+}
+
+#[ic_cdk::query]
+fn get_global_stats() -> StreamStats {
+    GLOBAL_STATS.with(|stats| stats.borrow().clone())
+}
+
+#[ic_cdk::query]
+fn get_user_stats(user: Principal) -> Option<UserStats> {
+    if !can_view_user(&user, &caller()) {
+        return None;
+    }
+    USER_STATS.with(|user_stats| user_stats.borrow().get(&user).cloned())
+}
+
+#[ic_cdk::query]
+fn get_my_stats() -> UserStats {
+    let user = caller();
+    get_user_stats(user).unwrap_or_else(|| empty_user_stats(user))
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum PauseResult {
+    #[serde(rename = "ok")]
+    Ok(()),
+    #[serde(rename = "err")]
+    Err(String),
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn pause_stream(stream_id: u64) -> PauseResult {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        match streams.get_mut(&stream_id) {
+            None => PauseResult::Err("Stream not found".to_string()),
+            Some(stream) => {
+                if let Err(e) = authorize(&stream.sender, &caller, DelegatePermission::PauseResume, 0) {
+                    return PauseResult::Err(e);
+                }
+                if stream.status != StreamStatus::Active {
+                    return PauseResult::Err("Stream is not active".to_string());
+                }
+                stream.status = StreamStatus::Paused;
+                stream.paused_at = Some(now);
+                update_stats_on_pause();
+                record_stream_event(stream, StreamEventKind::Paused, now);
+                PauseResult::Ok(())
+            }
+        }
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn resume_stream(stream_id: u64) -> PauseResult {
+    let caller = caller();
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        match streams.get_mut(&stream_id) {
+            None => PauseResult::Err("Stream not found".to_string()),
+            Some(stream) => {
+                if let Err(e) = authorize(&stream.sender, &caller, DelegatePermission::PauseResume, 0) {
+                    return PauseResult::Err(e);
+                }
+                if stream.status != StreamStatus::Paused {
+                    return PauseResult::Err("Stream is not paused".to_string());
+                }
+                let now = ic_cdk::api::time() / 1_000_000_000;
+                stream.status = StreamStatus::Active;
+                update_stats_on_resume();
+                stream.last_release_time = now;
+                if let Some(paused_at) = stream.paused_at.take() {
+                    stream.total_paused_secs += now.saturating_sub(paused_at);
+                }
+                record_stream_event(stream, StreamEventKind::Resumed, now);
+                PauseResult::Ok(())
+            }
+        }
+    })
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum MilestoneAction {
+    SendNotification(String),
+    AutoClaim,
+    PauseStream,
+    TopUpStream(u64),
+}
+
+fn milestone_permission(action: &MilestoneAction) -> (DelegatePermission, u64) {
+    match action {
+        MilestoneAction::SendNotification(_) => (DelegatePermission::UpdateMetadata, 0),
+        MilestoneAction::AutoClaim => (DelegatePermission::Claim, 0),
+        MilestoneAction::PauseStream => (DelegatePermission::PauseResume, 0),
+        MilestoneAction::TopUpStream(amount) => (DelegatePermission::TopUp, *amount),
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+struct Milestone {
+    id: u64,
+    stream_id: u64,
+    trigger_amount: u64, // Amount released to trigger this milestone
+    action: MilestoneAction,
+    triggered: bool,
+    created_by: Principal,
+}
+
+// Storage for milestones
+thread_local! {
+    static MILESTONES: std::cell::RefCell<HashMap<u64, Milestone>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_MILESTONE_ID: std::cell::RefCell<u64> = const { std::cell::RefCell::new(0) };
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+enum MilestoneResult {
+    #[serde(rename = "ok")]
+    Ok(u64),
+    #[serde(rename = "err")]
+    Err(String),
+    #[serde(rename = "quota_exceeded")]
+    QuotaExceeded(QuotaError),
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn add_milestone(stream_id: u64, trigger_amount: u64, action: MilestoneAction) -> MilestoneResult {
+    let creator = caller();
+    let Some((sender, recipient)) = STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| (s.sender, s.recipient))) else {
+        return MilestoneResult::Err("Stream not found".to_string());
+    };
+    // Either party, or a delegate allowed to take the action the milestone will trigger
+    let (permission, amount) = milestone_permission(&action);
+    if let Err(e) = authorize(&sender, &creator, permission, amount)
+        .or_else(|_| authorize(&recipient, &creator, permission, amount))
+    {
+        return MilestoneResult::Err(e);
+    }
+    let max_milestones = config().quotas.max_milestones_per_stream;
+    let existing = MILESTONES.with(|milestones| milestones.borrow().values().filter(|m| m.stream_id == stream_id).count());
+    if existing >= max_milestones as usize {
+        return MilestoneResult::QuotaExceeded(QuotaError::TooManyMilestones { limit: max_milestones });
+    }
+    let id = NEXT_MILESTONE_ID.with(|id| {
+        let mut id_mut = id.borrow_mut();
+        let curr = *id_mut;
+        *id_mut += 1;
+        curr
+    });
+    
+    let milestone = Milestone {
+        id,
+        stream_id,
+        trigger_amount,
+        action,
+        triggered: false,
+        created_by: creator,
+    };
+    
+    MILESTONES.with(|milestones| {
+        milestones.borrow_mut().insert(id, milestone);
+    });
+    
+    MilestoneResult::Ok(id)
+}
+
+fn check_and_execute_milestones(stream_id: u64, current_released: u64, now: u64) {
+    MILESTONES.with(|milestones| {
+        let mut milestones = milestones.borrow_mut();
+        for milestone in milestones.values_mut() {
+            if milestone.stream_id == stream_id 
+                && !milestone.triggered 
+                && current_released >= milestone.trigger_amount {
+                
+                milestone.triggered = true;
+                match &milestone.action {
+                    MilestoneAction::SendNotification(msg) => {
+                        create_notification(
+                            milestone.created_by,
+                            stream_id,
+                            NotificationType::StreamCreated,
+                            msg.clone(),
+                            now,
+                        );
+                    }
+                    MilestoneAction::AutoClaim => {
+                        // Auto-claim logic would go here
+                    }
+                    MilestoneAction::PauseStream => {
+                        // Auto-pause logic would go here
+                    }
+                    MilestoneAction::TopUpStream(_amount) => {
+                        // Auto-topup logic would go here
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+struct StreamFilter {
+    status: Option<StreamStatus>,
+    min_amount: Option<u64>,
+    max_amount: Option<u64>,
+    min_duration: Option<u64>,
+    max_duration: Option<u64>,
+    sender: Option<Principal>,
+    recipient: Option<Principal>,
+    created_after: Option<u64>,
+    created_before: Option<u64>,
+    tags_any: Option<Vec<String>>, // Matches streams carrying at least one of these tags
+    tags_all: Option<Vec<String>>, // Matches streams carrying every one of these tags
+    metadata: Option<Vec<(String, String)>>, // Every key must be present with exactly this value
+    text: Option<String>, // Case-insensitive substring of the title or description
+    min_claimable: Option<u64>,
+    max_claimable: Option<u64>,
+    min_rate: Option<u64>,
+    max_rate: Option<u64>,
+    ends_after: Option<u64>,
+    ends_before: Option<u64>,
+    has_unclaimed_buffer: Option<bool>,
+    counterparty: Option<Principal>, // Matches the principal as either sender or recipient
+}
+
+#[ic_cdk::query]
+fn search_streams(filter: StreamFilter, options: Option<StreamListOptions>) -> StreamPage {
+    let user = caller();
+    let options = options.unwrap_or_default();
+    // User must be involved in the stream
+    let mut ids: BTreeSet<u64> = USER_STREAMS.with(|index| index.borrow().get(&user).cloned().unwrap_or_default());
+
+    // Narrow the candidates through the tag index before looking at any stream
+    TAG_INDEX.with(|index| {
+        let index = index.borrow();
+        let tagged = |tag: &String| index.get(&normalize_tag(tag)).cloned().unwrap_or_default();
+        if let Some(tags) = filter.tags_all.as_ref() {
+            for tag in tags {
+                ids = ids.intersection(&tagged(tag)).copied().collect();
+            }
+        }
+        if let Some(tags) = filter.tags_any.as_ref().filter(|t| !t.is_empty()) {
+            let any: BTreeSet<u64> = tags.iter().flat_map(tagged).collect();
+            ids = ids.intersection(&any).copied().collect();
+        }
+    });
+
+    STREAMS.with(|streams| {
+        let streams = streams.borrow();
+        let candidates = ids
+            .iter()
+            .filter_map(|id| streams.get(id))
+            .filter(|s| stream_matches_filter(s, &filter))
+            .filter(|s| match options.role {
+                Some(StreamRole::Sender) => s.sender == user,
+                Some(StreamRole::Recipient) => s.recipient == user,
+                None => true,
+            })
+            .collect();
+        paginate_streams(candidates, &options)
+    })
+}
+
+fn stream_matches_filter(s: &Stream, filter: &StreamFilter) -> bool {
+    if let Some(status) = &filter.status {
+        if s.status != *status {
+            return false;
+        }
+    }
+    
+    if let Some(min_amount) = filter.min_amount {
+        if s.total_locked < min_amount {
+            return false;
+        }
+    }
+    
+    if let Some(max_amount) = filter.max_amount {
+        if s.total_locked > max_amount {
+            return false;
+        }
+    }
+    
+    let duration = s.end_time.saturating_sub(s.start_time);
+    if let Some(min_duration) = filter.min_duration {
+        if duration < min_duration {
+            return false;
+        }
+    }
+    
+    if let Some(max_duration) = filter.max_duration {
+        if duration > max_duration {
+            return false;
+        }
+    }
+    
+    if let Some(sender) = filter.sender {
+        if s.sender != sender {
+            return false;
+        }
+    }
+    
+    if let Some(recipient) = filter.recipient {
+        if s.recipient != recipient {
+            return false;
+        }
+    }
+    
+    if let Some(counterparty) = filter.counterparty {
+        if s.sender != counterparty && s.recipient != counterparty {
+            return false;
+        }
+    }
+    
+    if let Some(created_after) = filter.created_after {
+        if s.created_at < created_after {
+            return false;
+        }
+    }
+    
+    if let Some(created_before) = filter.created_before {
+        if s.created_at > created_before {
+            return false;
+        }
+    }
+    
+    if let Some(min_claimable) = filter.min_claimable {
+        if s.buffer < min_claimable {
+            return false;
+        }
+    }
+    
+    if let Some(max_claimable) = filter.max_claimable {
+        if s.buffer > max_claimable {
+            return false;
+        }
+    }
+    
+    if let Some(min_rate) = filter.min_rate {
+        if s.sats_per_sec < min_rate {
+            return false;
+        }
+    }
+    
+    if let Some(max_rate) = filter.max_rate {
+        if s.sats_per_sec > max_rate {
+            return false;
+        }
+    }
+    
+    if let Some(ends_after) = filter.ends_after {
+        if s.end_time < ends_after {
+            return false;
+        }
+    }
+    
+    if let Some(ends_before) = filter.ends_before {
+        if s.end_time > ends_before {
+            return false;
+        }
+    }
+    
+    if let Some(has_unclaimed_buffer) = filter.has_unclaimed_buffer {
+        if (s.buffer > 0) != has_unclaimed_buffer {
+            return false;
+        }
+    }
+    
+    if let Some(tags) = &filter.tags_any {
+        if !tags.is_empty() && !tags.iter().any(|t| stream_has_tag(s, t)) {
+            return false;
+        }
+    }
+    
+    if let Some(tags) = &filter.tags_all {
+        if !tags.iter().all(|t| stream_has_tag(s, t)) {
+            return false;
+        }
+    }
+    
+    if let Some(metadata) = &filter.metadata {
+        if !metadata.iter().all(|(k, v)| s.metadata.get(k) == Some(v)) {
+            return false;
+        }
+    }
+    
+    if let Some(text) = &filter.text {
+        let needle = text.to_lowercase();
+        let matches = |field: &Option<String>| field.as_ref().is_some_and(|f| f.to_lowercase().contains(&needle));
+        if !matches(&s.title) && !matches(&s.description) {
+            return false;
+        }
+    }
+    
+    true
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn update_stream_metadata(stream_id: u64, update: StreamMetadataUpdate) -> MetadataResult {
+    let caller = caller();
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let Some(stream) = streams.get_mut(&stream_id) else {
+            return MetadataResult::Err("Stream not found".to_string());
+        };
+        if let Err(e) = authorize(&stream.sender, &caller, DelegatePermission::UpdateMetadata, 0) {
+            return MetadataResult::Err(e);
+        }
+
+        // Validate the resulting state before touching the stream
+        let non_empty = |value: String| if value.is_empty() { None } else { Some(value) };
+        let title = update.title.map_or_else(|| stream.title.clone(), non_empty);
+        let description = update.description.map_or_else(|| stream.description.clone(), non_empty);
+        let tags = update.tags.unwrap_or_else(|| stream.tags.clone());
+        let mut metadata = stream.metadata.clone();
+        for key in &update.remove_metadata {
+            metadata.remove(key);
+        }
+        metadata.extend(update.set_metadata);
+        if let Err(e) = validate_stream_metadata(&title, &description, &tags, &metadata) {
+            return MetadataResult::Err(e);
+        }
+
+        unindex_stream_tags(stream_id, &stream.tags);
+        index_stream_tags(stream_id, &tags);
+        stream.title = title;
+        stream.description = description;
+        stream.tags = tags;
+        stream.metadata = metadata;
+        MetadataResult::Ok(())
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn set_recipient_annotations(stream_id: u64, set: Vec<(String, String)>, remove: Vec<String>) -> MetadataResult {
+    let caller = caller();
+    let Some(recipient) = STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.recipient)) else {
+        return MetadataResult::Err("Stream not found".to_string());
+    };
+    if let Err(e) = authorize(&recipient, &caller, DelegatePermission::UpdateMetadata, 0) {
+        return MetadataResult::Err(e);
+    }
+    RECIPIENT_ANNOTATIONS.with(|annotations| {
+        let mut annotations = annotations.borrow_mut();
+        let mut updated = annotations.get(&stream_id).cloned().unwrap_or_default();
+        for key in &remove {
+            updated.remove(key);
+        }
+        updated.extend(set);
+        if let Err(e) = validate_metadata_entries(&updated) {
+            return MetadataResult::Err(e);
+        }
+        if updated.is_empty() {
+            annotations.remove(&stream_id);
+        } else {
+            annotations.insert(stream_id, updated);
+        }
+        MetadataResult::Ok(())
+    })
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn set_stream_public(stream_id: u64, public: bool) -> MetadataResult {
+    let caller = caller();
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let Some(stream) = streams.get_mut(&stream_id) else {
+            return MetadataResult::Err("Stream not found".to_string());
+        };
+        if let Err(e) = authorize(&stream.sender, &caller, DelegatePermission::UpdateMetadata, 0) {
+            return MetadataResult::Err(e);
+        }
+        set_visibility(stream, public);
+        MetadataResult::Ok(())
+    })
+}
+
+fn set_visibility(stream: &mut Stream, public: bool) {
+    if stream.public == public {
+        return;
+    }
+    record_public_volume(stream, public);
+    stream.public = public;
+}
+
+#[ic_cdk::query]
+fn get_recipient_annotations(stream_id: u64) -> Vec<(String, String)> {
+    let caller = caller();
+    let Some(recipient) = STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.recipient)) else {
+        return Vec::new();
+    };
+    if authorize(&recipient, &caller, DelegatePermission::ViewStreams, 0).is_err() {
+        return Vec::new();
+    }
+    RECIPIENT_ANNOTATIONS.with(|annotations| {
+        annotations
+            .borrow()
+            .get(&stream_id)
+            .map(|a| a.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default()
+    })
+}
+
+fn validate_stream_metadata(
+    title: &Option<String>,
+    description: &Option<String>,
+    tags: &[String],
+    metadata: &HashMap<String, String>,
+) -> Result<(), String> {
+    let limits = config().limits;
+    if title.as_ref().is_some_and(|t| t.chars().count() > limits.max_title_len as usize) {
+        return Err(format!("Title must be at most {} characters", limits.max_title_len));
+    }
+    if description.as_ref().is_some_and(|d| d.chars().count() > limits.max_description_len as usize) {
+        return Err(format!("Description must be at most {} characters", limits.max_description_len));
+    }
+    if tags.len() > limits.max_tags as usize {
+        return Err(format!("At most {} tags are allowed", limits.max_tags));
+    }
+    if tags.iter().any(|t| t.trim().is_empty() || t.chars().count() > limits.max_tag_len as usize) {
+        return Err(format!("Tags must be non-empty and at most {} characters", limits.max_tag_len));
+    }
+    validate_metadata_entries(metadata)
+}
+
+fn validate_metadata_entries(metadata: &HashMap<String, String>) -> Result<(), String> {
+    let limits = config().limits;
+    if metadata.len() > limits.max_metadata_entries as usize {
+        return Err(format!("At most {} metadata entries are allowed", limits.max_metadata_entries));
+    }
+    for (key, value) in metadata {
+        if key.is_empty() || key.len() > limits.max_metadata_key_len as usize {
+            return Err(format!("Metadata keys must be non-empty and at most {} bytes", limits.max_metadata_key_len));
+        }
+        if value.len() > limits.max_metadata_value_len as usize {
+            return Err(format!("Metadata values must be at most {} bytes", limits.max_metadata_value_len));
+        }
+    }
+    Ok(())
+}
+
+fn unindex_stream_tags(stream_id: u64, tags: &[String]) {
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in tags {
+            let tag = normalize_tag(tag);
+            if let Some(ids) = index.get_mut(&tag) {
+                ids.remove(&stream_id);
+                if ids.is_empty() {
+                    index.remove(&tag);
+                }
+            }
+        }
+    });
+}
+
+fn index_stream_tags(stream_id: u64, tags: &[String]) {
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in tags {
+            index.entry(normalize_tag(tag)).or_default().insert(stream_id);
+        }
+    });
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+fn stream_has_tag(stream: &Stream, tag: &str) -> bool {
+    let tag = normalize_tag(tag);
+    stream.tags.iter().any(|t| normalize_tag(t) == tag)
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn create_template(name: String, description: String, duration_secs: u64, sats_per_sec: u64) -> TemplateResult {
+    let creator = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let max_templates = config().quotas.max_templates;
+    let owned = TEMPLATES.with(|templates| templates.borrow().values().filter(|t| t.creator == creator).count());
+    if owned >= max_templates as usize {
+        return TemplateResult::QuotaExceeded(QuotaError::TooManyTemplates { limit: max_templates });
+    }
+    
+    let id = NEXT_TEMPLATE_ID.with(|id| {
+        let mut id_mut = id.borrow_mut();
+        let curr = *id_mut;
+        *id_mut += 1;
+        curr
+    });
+    
+    let template = StreamTemplate {
+        id,
+        name,
+        description,
+        duration_secs,
+        sats_per_sec,
+        creator,
+        created_at: now,
+        usage_count: 0,
+    };
+    
+    TEMPLATES.with(|templates| {
+        templates.borrow_mut().insert(id, template);
+    });
+    
+    TemplateResult::Ok(id)
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn create_stream_from_template(template_id: u64, recipient: Principal, total_locked: u64) -> CreateStreamResult {
+    let Some(template) = TEMPLATES.with(|templates| templates.borrow().get(&template_id).cloned()) else {
+        return CreateStreamResult::Err("Template not found".to_string());
+    };
+    let result = create_stream(recipient, template.sats_per_sec, template.duration_secs, total_locked, None, None, Vec::new());
+    if let CreateStreamResult::Ok(_) = result {
+        TEMPLATES.with(|templates| {
+            if let Some(template) = templates.borrow_mut().get_mut(&template_id) {
+                template.usage_count += 1;
+            }
+        });
+    }
+    result
+}
+
+#[ic_cdk::query]
+fn list_templates() -> Vec<StreamTemplate> {
+    TEMPLATES.with(|templates| {
+        templates.borrow().values().cloned().collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_stream_stats(stream_id: u64) -> Option<StreamAnalytics> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let caller = caller();
+    STREAMS.with(|streams| {
+        streams
+            .borrow()
+            .get(&stream_id)
+            .filter(|s| can_view_stream(s, &caller))
+            .map(|stream| stream_analytics(stream, now))
+    })
+}
+
+fn stream_analytics(stream: &Stream, now: u64) -> StreamAnalytics {
+    let completion_percent = if stream.total_locked > 0 {
+        stream.total_released as f64 / stream.total_locked as f64 * 100.0
+    } else {
+        0.0
+    };
+    let average_claim_interval_secs = stream
+        .last_claim_time
+        .saturating_sub(stream.start_time)
+        .checked_div(stream.claim_count);
+    let time_paused_secs = stream.total_paused_secs
+        + stream.paused_at.map_or(0, |paused_at| now.saturating_sub(paused_at));
+
+    // Seconds until the locked funds run out at the current rate, bounded by end_time
+    let unreleased = stream.total_locked.saturating_sub(stream.total_released);
+    let funded_secs = if stream.sats_per_sec > 0 {
+        unreleased.div_ceil(stream.sats_per_sec)
+    } else {
+        0
+    };
+    let (projected_end_time, remaining_runway_secs) = match stream.status {
+        StreamStatus::Active => {
+            let end = stream.end_time.min(stream.last_release_time.saturating_add(funded_secs));
+            (Some(end), end.saturating_sub(now))
+        }
+        StreamStatus::Paused => {
+            // Assumes the stream is resumed now
+            let end = stream.end_time.min(now.saturating_add(funded_secs));
+            (Some(end), end.saturating_sub(now))
+        }
+        StreamStatus::Cancelled | StreamStatus::Completed => (None, 0),
+    };
+
+    StreamAnalytics {
+        stream_id: stream.id,
+        status: stream.status.clone(),
+        completion_percent,
+        total_locked: stream.total_locked,
+        total_released: stream.total_released,
+        claimed: stream.total_claimed,
+        unclaimed: stream.buffer,
+        reclaimed: stream.total_reclaimed,
+        claim_count: stream.claim_count,
+        average_claim_interval_secs,
+        time_paused_secs,
+        projected_end_time,
+        fees_charged: stream.fee_charged,
+        remaining_runway_secs,
+    }
+}
+
+fn create_notification(user: Principal, stream_id: u64, notification_type: NotificationType, message: String, now: u64) {
+    // Webhooks have their own event selection and are not affected by notification preferences
+    enqueue_webhook_deliveries(user, stream_id, &notification_type, &message, now);
+
+    if !notification_preferences_for(user).allows(&notification_type) {
+        return;
+    }
+
+    let id = NEXT_NOTIFICATION_ID.with(|id| {
+        let mut id_mut = id.borrow_mut();
+        let curr = *id_mut;
+        *id_mut += 1;
+        curr
+    });
+    
+    let notification = Notification {
+        id,
+        user,
+        stream_id,
+        notification_type,
+        message,
+        timestamp: now,
+        read: false,
+    };
+    
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow_mut().insert(id, notification);
+    });
+    USER_NOTIFICATIONS.with(|index| {
+        index.borrow_mut().entry(user).or_default().insert(id);
+    });
+}
+
+// Accrual between events is not published; clients extrapolate it from sats_per_sec and last_release_time
+#[ic_cdk::query]
+fn get_events_since(since: u64, limit: Option<u32>) -> EventFeedPage {
+    let user = caller();
+    let limit = limit.unwrap_or(DEFAULT_EVENT_PAGE_SIZE).clamp(1, DEFAULT_EVENT_PAGE_SIZE) as usize;
+    EVENT_FEEDS.with(|feeds| {
+        let feeds = feeds.borrow();
+        let Some(feed) = feeds.get(&user) else {
+            return EventFeedPage { events: Vec::new(), latest_seq: since, truncated: false };
+        };
+        let events: Vec<StreamEvent> = feed
+            .events
+            .iter()
+            .filter(|e| e.seq > since)
+            .take(limit)
+            .cloned()
+            .collect();
+        let latest_seq = events.last().map_or(since, |e| e.seq);
+        EventFeedPage { events, latest_seq, truncated: since < feed.evicted_through }
+    })
+}
+
+fn record_stream_event(stream: &Stream, kind: StreamEventKind, now: u64) {
+    record_stream_event_for(stream, kind, &[], now);
+}
+
+// Also delivers the event to principals who were a party until this change, e.g. a replaced recipient
+fn record_stream_event_for(stream: &Stream, kind: StreamEventKind, former_parties: &[Principal], now: u64) {
+    let seq = NEXT_EVENT_SEQ.with(|seq| {
+        let mut seq_mut = seq.borrow_mut();
+        let curr = *seq_mut;
+        *seq_mut += 1;
+        curr
+    });
+    let event = StreamEvent {
+        seq,
+        stream_id: stream.id,
+        kind,
+        delta: StreamDelta {
+            status: stream.status.clone(),
+            sats_per_sec: stream.sats_per_sec,
+            end_time: stream.end_time,
+            total_locked: stream.total_locked,
+            total_released: stream.total_released,
+            buffer: stream.buffer,
+            last_release_time: stream.last_release_time,
+            last_claim_time: stream.last_claim_time,
+        },
+        timestamp: now,
+    };
+    let mut parties: BTreeSet<Principal> = former_parties.iter().copied().collect();
+    parties.insert(stream.sender);
+    parties.insert(stream.recipient);
+    EVENT_FEEDS.with(|feeds| {
+        let mut feeds = feeds.borrow_mut();
+        for party in parties {
+            let feed = feeds.entry(party).or_default();
+            feed.events.push_back(event.clone());
+            if feed.events.len() > MAX_EVENTS_PER_USER {
+                if let Some(evicted) = feed.events.pop_front() {
+                    feed.evicted_through = evicted.seq;
+                }
+            }
+        }
+    });
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn register_webhook(url: String, secret: String, events: Vec<NotificationType>) -> WebhookResult {
+    let owner = caller();
+    if let Err(e) = validate_webhook_url(&url) {
+        return WebhookResult::Err(e);
+    }
+    if secret.len() < MIN_WEBHOOK_SECRET_LEN {
+        return WebhookResult::Err(format!("Secret must be at least {} characters", MIN_WEBHOOK_SECRET_LEN));
+    }
+    if events.is_empty() {
+        return WebhookResult::Err("At least one event type is required".to_string());
+    }
+    let registered = WEBHOOKS.with(|webhooks| webhooks.borrow().values().filter(|w| w.owner == owner).count());
+    let max_webhooks = config().limits.max_webhooks_per_user;
+    if registered >= max_webhooks as usize {
+        return WebhookResult::Err(format!("At most {} webhooks can be registered", max_webhooks));
+    }
+
+    let id = NEXT_WEBHOOK_ID.with(|id| {
+        let mut id_mut = id.borrow_mut();
+        let curr = *id_mut;
+        *id_mut += 1;
+        curr
+    });
+    let webhook = Webhook {
+        id,
+        owner,
+        url,
+        secret,
+        events,
+        created_at: ic_cdk::api::time() / 1_000_000_000,
+    };
+    WEBHOOKS.with(|webhooks| {
+        webhooks.borrow_mut().insert(id, webhook);
+    });
+    WebhookResult::Ok(id)
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn delete_webhook(webhook_id: u64) -> bool {
+    let owner = caller();
+    let removed = WEBHOOKS.with(|webhooks| {
+        let mut webhooks = webhooks.borrow_mut();
+        match webhooks.get(&webhook_id) {
+            Some(webhook) if webhook.owner == owner => webhooks.remove(&webhook_id).is_some(),
+            _ => false,
+        }
+    });
+    if removed {
+        let log = WEBHOOK_DELIVERY_LOG.with(|log| log.borrow_mut().remove(&webhook_id)).unwrap_or_default();
+        WEBHOOK_DELIVERIES.with(|deliveries| {
+            let mut deliveries = deliveries.borrow_mut();
+            for delivery_id in log {
+                deliveries.remove(&delivery_id);
+            }
+        });
+    }
+    removed
+}
+
+#[ic_cdk::query]
+fn list_webhooks() -> Vec<WebhookInfo> {
+    let owner = caller();
+    WEBHOOKS.with(|webhooks| {
+        webhooks
+            .borrow()
+            .values()
+            .filter(|w| w.owner == owner)
+            .map(|w| WebhookInfo {
+                id: w.id,
+                url: w.url.clone(),
+                events: w.events.clone(),
+                created_at: w.created_at,
+            })
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_webhook_deliveries(webhook_id: u64) -> Vec<WebhookDelivery> {
+    let owner = caller();
+    let owns = WEBHOOKS.with(|webhooks| webhooks.borrow().get(&webhook_id).is_some_and(|w| w.owner == owner));
+    if !owns {
+        return Vec::new();
+    }
+    let log = WEBHOOK_DELIVERY_LOG.with(|log| log.borrow().get(&webhook_id).cloned()).unwrap_or_default();
+    WEBHOOK_DELIVERIES.with(|deliveries| {
+        let deliveries = deliveries.borrow();
+        // Newest first
+        log.iter().rev().filter_map(|id| deliveries.get(id).cloned()).collect()
+    })
+}
+
+// Replicas must agree on the outcall response, so only the status code is kept
+#[ic_cdk::query]
+fn transform_webhook_response(args: outcall::TransformArgs) -> outcall::HttpResponse {
+    outcall::HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+// HTTPS is required; plain HTTP to a local stand-in is only accepted in builds with the `local` feature
+fn validate_webhook_url(url: &str) -> Result<(), String> {
+    if url.len() > MAX_WEBHOOK_URL_LEN {
+        return Err(format!("URL must be at most {} characters", MAX_WEBHOOK_URL_LEN));
+    }
+    if url.starts_with("https://") {
+        return Ok(());
+    }
+    let local = ["http://localhost", "http://127.0.0.1"];
+    if cfg!(any(test, feature = "local")) && local.iter().any(|prefix| {
+        url.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(':') || rest.starts_with('/'))
+    }) {
+        return Ok(());
+    }
+    Err("Webhook URL must use https://".to_string())
+}
+
+fn enqueue_webhook_deliveries(user: Principal, stream_id: u64, event: &NotificationType, message: &str, now: u64) {
+    let targets: Vec<u64> = WEBHOOKS.with(|webhooks| {
+        webhooks
+            .borrow()
+            .values()
+            .filter(|w| w.owner == user && w.events.contains(event))
+            .map(|w| w.id)
+            .collect()
+    });
+    if targets.is_empty() {
+        return;
+    }
+    let payload = serde_json::json!({
+        "event": format!("{:?}", event),
+        "stream_id": stream_id,
+        "user": user.to_text(),
+        "message": message,
+        "timestamp": now,
+    })
+    .to_string();
+
+    for webhook_id in targets {
+        let id = NEXT_WEBHOOK_DELIVERY_ID.with(|id| {
+            let mut id_mut = id.borrow_mut();
+            let curr = *id_mut;
+            *id_mut += 1;
+            curr
+        });
+        let delivery = WebhookDelivery {
+            id,
+            webhook_id,
+            stream_id,
+            event: event.clone(),
+            payload: payload.clone(),
+            status: WebhookDeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            in_flight_since: None,
+            last_status_code: None,
+            last_error: None,
+            created_at: now,
+            delivered_at: None,
+        };
+        WEBHOOK_DELIVERIES.with(|deliveries| {
+            deliveries.borrow_mut().insert(id, delivery);
+        });
+        let evicted = WEBHOOK_DELIVERY_LOG.with(|log| {
+            let mut log = log.borrow_mut();
+            let entries = log.entry(webhook_id).or_default();
+            entries.push_back(id);
+            if entries.len() > MAX_WEBHOOK_LOG_ENTRIES {
+                entries.pop_front()
+            } else {
+                None
+            }
+        });
+        if let Some(evicted) = evicted {
+            WEBHOOK_DELIVERIES.with(|deliveries| {
+                deliveries.borrow_mut().remove(&evicted);
+            });
+        }
+    }
+}
+
+fn webhook_backoff_secs(attempts: u32) -> u64 {
+    WEBHOOK_BASE_BACKOFF_SECS << attempts.saturating_sub(1)
+}
+
+fn webhook_signature(secret: &str, timestamp: &str, payload: &str) -> String {
+    let signed = format!("{}.{}", timestamp, payload);
+    hmac_sha256::HMAC::mac(signed.as_bytes(), secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Counts one attempt and either completes the delivery or schedules the next retry
+fn record_webhook_attempt(delivery: &mut WebhookDelivery, outcome: Result<u64, String>, now: u64) {
+    delivery.attempts += 1;
+    delivery.in_flight_since = None;
+    match outcome {
+        Ok(status) if (200..300).contains(&status) => {
+            delivery.status = WebhookDeliveryStatus::Delivered;
+            delivery.last_status_code = Some(status);
+            delivery.last_error = None;
+            delivery.delivered_at = Some(now);
+            return;
+        }
+        Ok(status) => {
+            delivery.last_status_code = Some(status);
+            delivery.last_error = Some(format!("Endpoint responded with status {}", status));
+        }
+        Err(e) => {
+            delivery.last_status_code = None;
+            delivery.last_error = Some(e);
+        }
+    }
+    if delivery.attempts >= MAX_WEBHOOK_ATTEMPTS {
+        delivery.status = WebhookDeliveryStatus::Failed;
+    } else {
+        delivery.status = WebhookDeliveryStatus::Pending;
+        delivery.next_attempt_at = now + webhook_backoff_secs(delivery.attempts);
+    }
+}
+
+// An upgrade or a trap in the callback can drop an outcall's result, leaving its delivery in flight
+fn requeue_stuck_webhook_deliveries(deliveries: &mut HashMap<u64, WebhookDelivery>, now: u64) {
+    for delivery in deliveries.values_mut() {
+        let stuck = delivery.status == WebhookDeliveryStatus::InFlight
+            && delivery.in_flight_since.is_none_or(|since| since + WEBHOOK_IN_FLIGHT_TIMEOUT_SECS <= now);
+        if stuck {
+            record_webhook_attempt(delivery, Err("Timed out waiting for the endpoint".to_string()), now);
+        }
+    }
+}
+
+// The clock and the HTTPS outcall used for webhook delivery; tests substitute a local stand-in
+trait WebhookTransport {
+    fn now(&self) -> u64;
+    async fn post(&self, request: outcall::CanisterHttpRequestArgument) -> Result<u64, String>;
+}
+
+struct OutcallTransport;
+
+impl WebhookTransport for OutcallTransport {
+    fn now(&self) -> u64 {
+        ic_cdk::api::time() / 1_000_000_000
+    }
+
+    async fn post(&self, mut request: outcall::CanisterHttpRequestArgument) -> Result<u64, String> {
+        request.transform = Some(outcall::TransformContext::from_name("transform_webhook_response".to_string(), Vec::new()));
+        match outcall::http_request(request, WEBHOOK_OUTCALL_CYCLES).await {
+            Ok((response,)) => response
+                .status
+                .0
+                .try_into()
+                .map_err(|_| "Invalid status code".to_string()),
+            Err((code, msg)) => Err(format!("{:?}: {}", code, msg)),
+        }
+    }
+}
+
+async fn dispatch_webhooks() {
+    count_timer_run("webhook_dispatch");
+    dispatch_webhooks_with(&OutcallTransport).await;
+}
+
+async fn dispatch_webhooks_with(transport: &impl WebhookTransport) {
+    let now = transport.now();
+    // Mark the batch in flight first so an overlapping timer tick does not send it twice
+    let due: Vec<WebhookDelivery> = WEBHOOK_DELIVERIES.with(|deliveries| {
+        let mut deliveries = deliveries.borrow_mut();
+        requeue_stuck_webhook_deliveries(&mut deliveries, now);
+        let mut due: Vec<&mut WebhookDelivery> = deliveries
+            .values_mut()
+            .filter(|d| d.status == WebhookDeliveryStatus::Pending && d.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|d| d.id);
+        due.into_iter()
+            .take(WEBHOOK_DISPATCH_BATCH)
+            .map(|d| {
+                d.status = WebhookDeliveryStatus::InFlight;
+                d.in_flight_since = Some(now);
+                d.clone()
+            })
+            .collect()
+    });
+
+    for delivery in due {
+        let Some(webhook) = WEBHOOKS.with(|webhooks| webhooks.borrow().get(&delivery.webhook_id).cloned()) else {
+            continue;
+        };
+        let outcome = send_webhook(transport, &webhook, &delivery).await;
+        let now = transport.now();
+        WEBHOOK_DELIVERIES.with(|deliveries| {
+            let mut deliveries = deliveries.borrow_mut();
+            // Skip results for deliveries that were already requeued after timing out
+            if let Some(delivery) = deliveries
+                .get_mut(&delivery.id)
+                .filter(|d| d.status == WebhookDeliveryStatus::InFlight)
+            {
+                record_webhook_attempt(delivery, outcome, now);
+            }
+        });
+    }
+}
+
+// Every replica performs the outcall, so receivers should dedupe on X-SatoshiFlow-Delivery
+async fn send_webhook(transport: &impl WebhookTransport, webhook: &Webhook, delivery: &WebhookDelivery) -> Result<u64, String> {
+    let timestamp = transport.now().to_string();
+    let signature = webhook_signature(&webhook.secret, &timestamp, &delivery.payload);
+
+    let request = outcall::CanisterHttpRequestArgument {
+        url: webhook.url.clone(),
+        method: outcall::HttpMethod::POST,
+        body: Some(delivery.payload.clone().into_bytes()),
+        max_response_bytes: Some(WEBHOOK_MAX_RESPONSE_BYTES),
+        transform: None,
+        headers: vec![
+            outcall::HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+            outcall::HttpHeader { name: "X-SatoshiFlow-Delivery".to_string(), value: delivery.id.to_string() },
+            outcall::HttpHeader { name: "X-SatoshiFlow-Timestamp".to_string(), value: timestamp },
+            outcall::HttpHeader { name: "X-SatoshiFlow-Signature".to_string(), value: format!("sha256={}", signature) },
+        ],
+    };
+    transport.post(request).await
+}
+
+fn count_timer_run(job: &'static str) {
+    TIMER_RUNS.with(|runs| *runs.borrow_mut().entry(job).or_default() += 1);
+}
+
+// Served as an uncertified query, so scrape it through the raw domain
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    if request.method != "GET" || path != "/metrics" {
+        return HttpResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"Not found".to_vec(),
+        };
+    }
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".to_string(), "text/plain; version=0.0.4".to_string())],
+        body: render_metrics().into_bytes(),
+    }
+}
+
+fn render_metrics() -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, samples: &[(&str, u128)]| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{}{} {}", name, labels, value);
+        }
+    };
+
+    let (active, paused, cancelled, completed, escrowed) = STREAMS.with(|streams| {
+        let mut counts = (0u128, 0u128, 0u128, 0u128, 0u128);
+        for stream in streams.borrow().values() {
+            match stream.status {
+                StreamStatus::Active => counts.0 += 1,
+                StreamStatus::Paused => counts.1 += 1,
+                StreamStatus::Cancelled => counts.2 += 1,
+                StreamStatus::Completed => counts.3 += 1,
+            }
+            // A cancelled stream only still holds its unclaimed buffer; the rest was refunded
+            counts.4 += match stream.status {
+                StreamStatus::Cancelled => stream.buffer as u128,
+                _ => stream
+                    .total_locked
+                    .saturating_sub(stream.total_claimed)
+                    .saturating_sub(stream.total_reclaimed) as u128,
+            };
+        }
+        counts
+    });
+    gauge(
+        "satoshiflow_streams",
+        "Number of streams by status.",
+        &[
+            ("{status=\"Active\"}", active),
+            ("{status=\"Paused\"}", paused),
+            ("{status=\"Cancelled\"}", cancelled),
+            ("{status=\"Completed\"}", completed),
+        ],
+    );
+    gauge("satoshiflow_escrowed_sats", "Sats currently held in escrow by the canister.", &[("", escrowed)]);
+
+    let notifications = NOTIFICATIONS.with(|n| n.borrow().len() as u128);
+    let milestones = MILESTONES.with(|m| m.borrow().len() as u128);
+    let templates = TEMPLATES.with(|t| t.borrow().len() as u128);
+    let pending_webhooks = WEBHOOK_DELIVERIES.with(|d| {
+        d.borrow()
+            .values()
+            .filter(|d| d.status == WebhookDeliveryStatus::Pending || d.status == WebhookDeliveryStatus::InFlight)
+            .count() as u128
+    });
+    gauge("satoshiflow_notifications_stored", "Entries in the notification map.", &[("", notifications)]);
+    gauge("satoshiflow_milestones_stored", "Entries in the milestone map.", &[("", milestones)]);
+    gauge("satoshiflow_templates_stored", "Entries in the template map.", &[("", templates)]);
+    gauge("satoshiflow_webhook_deliveries_pending", "Webhook deliveries waiting to be sent.", &[("", pending_webhooks)]);
+
+    let halted = HALT_STATE.with(|state| state.borrow().halted.clone());
+    let halt_samples: Vec<(String, u128)> = [
+        HaltableOperation::CreateStream,
+        HaltableOperation::Claim,
+        HaltableOperation::TopUp,
+        HaltableOperation::Cancel,
+    ]
+    .iter()
+    .map(|op| (format!("{{operation=\"{:?}\"}}", op), halted.contains(op) as u128))
+    .collect();
+    let halt_samples: Vec<(&str, u128)> = halt_samples.iter().map(|(l, v)| (l.as_str(), *v)).collect();
+    gauge("satoshiflow_operation_halted", "1 while the circuit breaker halts the operation.", &halt_samples);
+
+    #[cfg(target_arch = "wasm32")]
+    let heap_bytes = core::arch::wasm32::memory_size(0) as u128 * 65536;
+    #[cfg(not(target_arch = "wasm32"))]
+    let heap_bytes = 0u128;
+    let stable_bytes = ic_cdk::api::stable::stable_size() as u128 * 65536;
+    gauge("satoshiflow_heap_memory_bytes", "Size of the wasm heap in bytes.", &[("", heap_bytes)]);
+    gauge("satoshiflow_stable_memory_bytes", "Size of stable memory in bytes.", &[("", stable_bytes)]);
+    gauge("satoshiflow_cycles_balance", "Cycles balance of the canister.", &[("", ic_cdk::api::canister_balance128())]);
+
+    let heartbeats = HEARTBEAT_RUNS.with(|runs| *runs.borrow() as u128);
+    let _ = writeln!(out, "# HELP satoshiflow_heartbeat_executions_total Heartbeat executions since install or upgrade.");
+    let _ = writeln!(out, "# TYPE satoshiflow_heartbeat_executions_total counter");
+    let _ = writeln!(out, "satoshiflow_heartbeat_executions_total {}", heartbeats);
+    let _ = writeln!(out, "# HELP satoshiflow_timer_executions_total Timer job executions since install or upgrade.");
+    let _ = writeln!(out, "# TYPE satoshiflow_timer_executions_total counter");
+    TIMER_RUNS.with(|runs| {
+        for (job, count) in runs.borrow().iter() {
+            let _ = writeln!(out, "satoshiflow_timer_executions_total{{job=\"{}\"}} {}", job, count);
+        }
+    });
+    out
+}
+
+fn record_audit_entry(stream_id: u64, actor: Principal, action: AuditAction, now: u64) {
+    let entry = AuditEntry { timestamp: now, actor, action };
+    AUDIT_LOG.with(|log| log.borrow_mut().entry(stream_id).or_default().push(entry));
+}
+
+#[ic_cdk::query]
+fn get_stream_audit_log(stream_id: u64) -> Vec<AuditEntry> {
+    let caller = caller();
+    let visible = STREAMS.with(|streams| streams.borrow().get(&stream_id).is_some_and(|s| can_view_stream(s, &caller)));
+    if !visible {
+        return Vec::new();
+    }
+    AUDIT_LOG.with(|log| log.borrow().get(&stream_id).cloned().unwrap_or_default())
+}
+
+// Step one of handing control and refund rights to a new principal; multisig streams use a TransferSender proposal.
+// Owner-only: delegates cannot hand the stream away.
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn propose_sender_transfer(stream_id: u64, new_sender: Principal) -> SenderTransferResult {
+    let caller = caller();
+    match STREAMS.with(|streams| streams.borrow().get(&stream_id).map(|s| s.sender)) {
+        None => return SenderTransferResult::Err("Stream not found".to_string()),
+        Some(sender) if sender != caller => {
+            return SenderTransferResult::Err("Only the sender can transfer the stream".to_string());
+        }
+        Some(_) => {}
+    }
+    if stream_owners(stream_id).is_some() {
+        return SenderTransferResult::Err("Transferring a multisig stream requires an owner proposal".to_string());
+    }
+    match start_sender_transfer(stream_id, new_sender, caller) {
+        Ok(()) => SenderTransferResult::Ok(()),
+        Err(e) => SenderTransferResult::Err(e),
+    }
+}
+
+fn start_sender_transfer(stream_id: u64, new_sender: Principal, actor: Principal) -> Result<(), String> {
+    let (sender, recipient, status) = STREAMS
+        .with(|streams| streams.borrow().get(&stream_id).map(|s| (s.sender, s.recipient, s.status.clone())))
+        .ok_or("Stream not found")?;
+    if new_sender == Principal::anonymous() || new_sender == sender {
+        return Err("Invalid new sender".to_string());
+    }
+    if status == StreamStatus::Cancelled || status == StreamStatus::Completed {
+        return Err("Stream has already ended".to_string());
+    }
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    prune_expired_sender_transfers(now);
+    let pending = PendingSenderTransfer {
+        stream_id,
+        from: sender,
+        to: new_sender,
+        proposed_at: now,
+        expires_at: now + SENDER_TRANSFER_TTL_SECS,
+    };
+    // A new proposal replaces any earlier one
+    PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow_mut().insert(stream_id, pending));
+    record_audit_entry(stream_id, actor, AuditAction::SenderTransferProposed { from: sender, to: new_sender }, now);
+    let message = format!("Sender transfer of stream {} proposed from {} to {}", stream_id, sender, new_sender);
+    notify_parties(&[sender, recipient, new_sender], stream_id, NotificationType::SenderTransfer, &message, now);
+    Ok(())
+}
+
+fn prune_expired_sender_transfers(now: u64) {
+    PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow_mut().retain(|_, p| now < p.expires_at));
+}
+
+// The new sender takes over the previous sender's seat, so the threshold and the other owners are unchanged
+fn replace_owner(owners: &StreamOwners, from: Principal, to: Principal) -> Result<StreamOwners, String> {
+    let mut updated = owners.clone();
+    for owner in updated.owners.iter_mut() {
+        if *owner == from {
+            *owner = to;
+        }
+    }
+    validate_stream_owners(updated, &to)
+}
+
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn accept_sender_transfer(stream_id: u64) -> SenderTransferResult {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    prune_expired_sender_transfers(now);
+    let pending = PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow().get(&stream_id).cloned());
+    let Some(pending) = pending.filter(|p| p.to == caller) else {
+        return SenderTransferResult::Err("No pending sender transfer for caller".to_string());
+    };
+    let owners = match stream_owners(stream_id).map(|o| replace_owner(&o, pending.from, caller)).transpose() {
+        Ok(owners) => owners,
+        Err(e) => return SenderTransferResult::Err(e),
+    };
+    let result = STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let stream = streams.get_mut(&stream_id).ok_or("Stream not found")?;
+        if stream.sender != pending.from {
+            return Err("Stream sender changed since the transfer was proposed".to_string());
+        }
+        if stream.status == StreamStatus::Cancelled || stream.status == StreamStatus::Completed {
+            return Err("Stream has already ended".to_string());
+        }
+        stream.sender = caller;
+        USER_STREAMS.with(|index| {
+            let mut index = index.borrow_mut();
+            if pending.from != stream.recipient {
+                if let Some(ids) = index.get_mut(&pending.from) {
+                    ids.remove(&stream_id);
+                }
+            }
+            index.entry(caller).or_default().insert(stream_id);
+        });
+        record_stream_event_for(stream, StreamEventKind::SenderTransferred, &[pending.from], now);
+        Ok(stream.recipient)
+    });
+    PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow_mut().remove(&stream_id));
+    let recipient = match result {
+        Ok(recipient) => recipient,
+        Err(e) => return SenderTransferResult::Err(e),
+    };
+    if let Some(owners) = owners {
+        STREAM_OWNERS.with(|all| all.borrow_mut().insert(stream_id, owners));
+    }
+    record_audit_entry(stream_id, caller, AuditAction::SenderTransferred { from: pending.from, to: caller }, now);
+    let message = format!("Stream {} sender changed from {} to {}", stream_id, pending.from, caller);
+    notify_parties(&[pending.from, recipient, caller], stream_id, NotificationType::SenderTransfer, &message, now);
+    SenderTransferResult::Ok(())
+}
+
+// Either side may withdraw: the current sender, or the proposed new sender declining
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn cancel_sender_transfer(stream_id: u64) -> SenderTransferResult {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    prune_expired_sender_transfers(now);
+    let pending = PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow().get(&stream_id).cloned());
+    let Some(pending) = pending else {
+        return SenderTransferResult::Err("No pending sender transfer".to_string());
+    };
+    let is_owner = stream_owners(stream_id).is_some_and(|o| o.owners.contains(&caller));
+    if caller != pending.from && caller != pending.to && !is_owner {
+        return SenderTransferResult::Err("Not authorized to cancel this transfer".to_string());
+    }
+    PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow_mut().remove(&stream_id));
+    record_audit_entry(stream_id, caller, AuditAction::SenderTransferCancelled { from: pending.from, to: pending.to }, now);
+    let message = format!("Sender transfer of stream {} to {} was cancelled", stream_id, pending.to);
+    notify_parties(&[pending.from, pending.to], stream_id, NotificationType::SenderTransfer, &message, now);
+    SenderTransferResult::Ok(())
+}
+
+#[ic_cdk::query]
+fn get_pending_sender_transfer(stream_id: u64) -> Option<PendingSenderTransfer> {
+    let caller = caller();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let pending = PENDING_SENDER_TRANSFERS
+        .with(|transfers| transfers.borrow().get(&stream_id).cloned())
+        .filter(|p| now < p.expires_at)?;
+    let visible = pending.to == caller
+        || STREAMS.with(|streams| streams.borrow().get(&stream_id).is_some_and(|s| can_view_stream(s, &caller)));
+    visible.then_some(pending)
+}
+
+fn token_owner(stream: &Stream) -> Account {
+    Account {
+        owner: stream.recipient,
+        subaccount: TOKEN_SUBACCOUNTS.with(|subaccounts| subaccounts.borrow().get(&stream.id).cloned()),
+    }
+}
+
+// ICRC-1 treats a missing subaccount and 32 zero bytes as the same account
+fn normalize_subaccount(subaccount: Option<Vec<u8>>) -> Option<Vec<u8>> {
+    subaccount.filter(|s| s.iter().any(|b| *b != 0))
+}
+
+fn nat_to_u64(n: &Nat) -> Option<u64> {
+    u64::try_from(&n.0).ok()
+}
+
+fn check_query_batch<T>(items: &[T]) {
+    if items.len() > ICRC7_MAX_QUERY_BATCH_SIZE {
+        ic_cdk::trap(&format!("Batch size exceeds {}", ICRC7_MAX_QUERY_BATCH_SIZE));
+    }
+}
+
+#[ic_cdk::query]
+fn icrc7_collection_metadata() -> Vec<(String, Value)> {
+    vec![
+        ("icrc7:symbol".to_string(), Value::Text(ICRC7_SYMBOL.to_string())),
+        ("icrc7:name".to_string(), Value::Text(ICRC7_NAME.to_string())),
+        ("icrc7:description".to_string(), Value::Text(ICRC7_DESCRIPTION.to_string())),
+        ("icrc7:total_supply".to_string(), Value::Nat(icrc7_total_supply())),
+        ("icrc7:max_query_batch_size".to_string(), Value::Nat(Nat::from(ICRC7_MAX_QUERY_BATCH_SIZE))),
+        ("icrc7:max_update_batch_size".to_string(), Value::Nat(Nat::from(ICRC7_MAX_UPDATE_BATCH_SIZE))),
+        ("icrc7:default_take_value".to_string(), Value::Nat(Nat::from(ICRC7_DEFAULT_TAKE))),
+        ("icrc7:max_take_value".to_string(), Value::Nat(Nat::from(ICRC7_MAX_TAKE))),
+        ("icrc7:max_memo_size".to_string(), Value::Nat(Nat::from(ICRC7_MAX_MEMO_SIZE))),
+        ("icrc7:tx_window".to_string(), Value::Nat(Nat::from(ICRC7_TX_WINDOW_NANOS))),
+        ("icrc7:permitted_drift".to_string(), Value::Nat(Nat::from(ICRC7_PERMITTED_DRIFT_NANOS))),
+    ]
+}
+
+#[ic_cdk::query]
+fn icrc7_symbol() -> String {
+    ICRC7_SYMBOL.to_string()
+}
+
+#[ic_cdk::query]
+fn icrc7_name() -> String {
+    ICRC7_NAME.to_string()
+}
+
+#[ic_cdk::query]
+fn icrc7_description() -> Option<String> {
+    Some(ICRC7_DESCRIPTION.to_string())
+}
+
+#[ic_cdk::query]
+fn icrc7_logo() -> Option<String> {
+    None
+}
+
+// Streams are never deleted, so every id ever allocated is a live token
+#[ic_cdk::query]
+fn icrc7_total_supply() -> Nat {
+    Nat::from(NEXT_ID.with(|id| *id.borrow()))
+}
+
+#[ic_cdk::query]
+fn icrc7_supply_cap() -> Option<Nat> {
+    None
+}
+
+#[ic_cdk::query]
+fn icrc7_max_query_batch_size() -> Option<Nat> {
+    Some(Nat::from(ICRC7_MAX_QUERY_BATCH_SIZE))
+}
+
+#[ic_cdk::query]
+fn icrc7_max_update_batch_size() -> Option<Nat> {
+    Some(Nat::from(ICRC7_MAX_UPDATE_BATCH_SIZE))
+}
+
+#[ic_cdk::query]
+fn icrc7_default_take_value() -> Option<Nat> {
+    Some(Nat::from(ICRC7_DEFAULT_TAKE))
+}
+
+#[ic_cdk::query]
+fn icrc7_max_take_value() -> Option<Nat> {
+    Some(Nat::from(ICRC7_MAX_TAKE))
+}
+
+#[ic_cdk::query]
+fn icrc7_max_memo_size() -> Option<Nat> {
+    Some(Nat::from(ICRC7_MAX_MEMO_SIZE))
+}
+
+#[ic_cdk::query]
+fn icrc7_atomic_batch_transfers() -> Option<bool> {
+    Some(false)
+}
+
+#[ic_cdk::query]
+fn icrc7_tx_window() -> Option<Nat> {
+    Some(Nat::from(ICRC7_TX_WINDOW_NANOS))
+}
+
+#[ic_cdk::query]
+fn icrc7_permitted_drift() -> Option<Nat> {
+    Some(Nat::from(ICRC7_PERMITTED_DRIFT_NANOS))
+}
+
+// Private streams follow the same visibility rule as get_stream: other callers only learn that the
+// token exists, not its details, owner or place in anyone's balance
+#[ic_cdk::query]
+fn icrc7_token_metadata(token_ids: Vec<Nat>) -> Vec<Option<Vec<(String, Value)>>> {
+    check_query_batch(&token_ids);
+    let caller = caller();
+    STREAMS.with(|streams| {
+        let streams = streams.borrow();
+        token_ids
+            .iter()
+            .map(|token_id| {
+                let stream = nat_to_u64(token_id).and_then(|id| streams.get(&id))?;
+                if !can_view_stream(stream, &caller) {
+                    return Some(vec![("icrc7:name".to_string(), Value::Text(format!("Stream #{}", stream.id)))]);
+                }
+                let remaining = stream.total_locked.saturating_sub(stream.total_released);
+                let name = stream.title.clone().unwrap_or_else(|| format!("Stream #{}", stream.id));
+                let mut metadata = vec![
+                    ("icrc7:name".to_string(), Value::Text(name)),
+                    ("satoshiflow:sender".to_string(), Value::Blob(stream.sender.as_slice().to_vec())),
+                    ("satoshiflow:status".to_string(), Value::Text(format!("{:?}", stream.status))),
+                    ("satoshiflow:sats_per_sec".to_string(), Value::Nat(Nat::from(stream.sats_per_sec))),
+                    ("satoshiflow:remaining".to_string(), Value::Nat(Nat::from(remaining))),
+                    ("satoshiflow:claimable".to_string(), Value::Nat(Nat::from(stream.buffer))),
+                    ("satoshiflow:end_time".to_string(), Value::Nat(Nat::from(stream.end_time))),
+                ];
+                if let Some(description) = &stream.description {
+                    metadata.push(("icrc7:description".to_string(), Value::Text(description.clone())));
+                }
+                Some(metadata)
+            })
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn icrc7_owner_of(token_ids: Vec<Nat>) -> Vec<Option<Account>> {
+    check_query_batch(&token_ids);
+    let caller = caller();
+    STREAMS.with(|streams| {
+        let streams = streams.borrow();
+        token_ids
+            .iter()
+            .map(|token_id| {
+                nat_to_u64(token_id)
+                    .and_then(|id| streams.get(&id))
+                    .filter(|s| can_view_stream(s, &caller))
+                    .map(token_owner)
+            })
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn icrc7_balance_of(accounts: Vec<Account>) -> Vec<Nat> {
+    check_query_batch(&accounts);
+    let caller = caller();
+    accounts
+        .into_iter()
+        .map(|account| {
+            Nat::from(tokens_of_account(&account, None, u64::MAX, |s| can_view_stream(s, &caller)).len())
+        })
+        .collect()
+}
+
+#[ic_cdk::query]
+fn icrc7_tokens(prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    let start = match prev {
+        Some(prev) => nat_to_u64(&prev).map_or(u64::MAX, |p| p.saturating_add(1)),
+        None => 0,
+    };
+    let end = NEXT_ID.with(|id| *id.borrow());
+    (start..end).take(take_value(take) as usize).map(Nat::from).collect()
+}
+
+#[ic_cdk::query]
+fn icrc7_tokens_of(account: Account, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    let caller = caller();
+    let prev = prev.map(|p| nat_to_u64(&p).unwrap_or(u64::MAX));
+    tokens_of_account(&account, prev, take_value(take), |s| can_view_stream(s, &caller))
+        .into_iter()
+        .map(Nat::from)
+        .collect()
+}
+
+fn take_value(take: Option<Nat>) -> u64 {
+    take.and_then(|t| nat_to_u64(&t)).unwrap_or(ICRC7_DEFAULT_TAKE).min(ICRC7_MAX_TAKE)
+}
+
+fn tokens_of_account(account: &Account, prev: Option<u64>, take: u64, visible: impl Fn(&Stream) -> bool) -> Vec<u64> {
+    let account = Account { owner: account.owner, subaccount: normalize_subaccount(account.subaccount.clone()) };
+    let ids: Vec<u64> = USER_STREAMS.with(|index| {
+        index
+            .borrow()
+            .get(&account.owner)
+            .map(|ids| ids.iter().copied().filter(|id| prev.is_none_or(|p| *id > p)).collect())
+            .unwrap_or_default()
+    });
+    STREAMS.with(|streams| {
+        let streams = streams.borrow();
+        ids.into_iter()
+            .filter(|id| streams.get(id).is_some_and(|s| token_owner(s) == account && visible(s)))
+            .take(take as usize)
+            .collect()
+    })
+}
+
+// Transferring a token moves the recipient rights: the accrued buffer is settled to the
+// previous owner and the stream's recipient becomes the new owner.
+#[ic_cdk::update(guard = "reject_anonymous")]
+fn icrc7_transfer(args: Vec<Icrc7TransferArg>) -> Vec<Option<Icrc7TransferResult>> {
+    if args.len() > ICRC7_MAX_UPDATE_BATCH_SIZE {
+        ic_cdk::trap(&format!("Batch size exceeds {}", ICRC7_MAX_UPDATE_BATCH_SIZE));
+    }
+    let caller = caller();
+    let now_nanos = ic_cdk::api::time();
+    RECENT_TOKEN_TRANSFERS.with(|recent| {
+        let cutoff = now_nanos.saturating_sub(ICRC7_TX_WINDOW_NANOS + ICRC7_PERMITTED_DRIFT_NANOS);
+        recent.borrow_mut().retain(|(_, arg, _)| arg.created_at_time.is_some_and(|t| t >= cutoff));
+    });
+    args.into_iter()
+        .map(|arg| {
+            Some(match transfer_token(caller, arg, now_nanos) {
+                Ok(tx) => Icrc7TransferResult::Ok(Nat::from(tx)),
+                Err(e) => Icrc7TransferResult::Err(e),
+            })
+        })
+        .collect()
+}
+
+fn transfer_token(caller: Principal, arg: Icrc7TransferArg, now_nanos: u64) -> Result<u64, Icrc7TransferError> {
+    if arg.memo.as_ref().is_some_and(|m| m.len() > ICRC7_MAX_MEMO_SIZE) {
+        return Err(Icrc7TransferError::GenericError {
+            error_code: Nat::from(1u64),
+            message: format!("Memo exceeds {} bytes", ICRC7_MAX_MEMO_SIZE),
+        });
+    }
+    if let Some(created_at_time) = arg.created_at_time {
+        if created_at_time.saturating_add(ICRC7_TX_WINDOW_NANOS + ICRC7_PERMITTED_DRIFT_NANOS) < now_nanos {
+            return Err(Icrc7TransferError::TooOld);
+        }
+        if created_at_time > now_nanos.saturating_add(ICRC7_PERMITTED_DRIFT_NANOS) {
+            return Err(Icrc7TransferError::CreatedInFuture { ledger_time: now_nanos });
+        }
+        let duplicate = RECENT_TOKEN_TRANSFERS.with(|recent| {
+            recent.borrow().iter().find(|(c, a, _)| *c == caller && *a == arg).map(|(_, _, tx)| *tx)
+        });
+        if let Some(tx) = duplicate {
+            return Err(Icrc7TransferError::Duplicate { duplicate_of: Nat::from(tx) });
+        }
+    }
+    let stream_id = nat_to_u64(&arg.token_id).ok_or(Icrc7TransferError::NonExistingTokenId)?;
+    let to = Account { owner: arg.to.owner, subaccount: normalize_subaccount(arg.to.subaccount.clone()) };
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let stream = streams.get_mut(&stream_id).ok_or(Icrc7TransferError::NonExistingTokenId)?;
+        let from = token_owner(stream);
+        if from.owner != caller || from.subaccount != normalize_subaccount(arg.from_subaccount.clone()) {
+            return Err(Icrc7TransferError::Unauthorized);
+        }
+        if to.owner == Principal::anonymous() || to == from {
+            return Err(Icrc7TransferError::InvalidRecipient);
+        }
+        if to.owner != from.owner {
+            reassign_recipient(stream, to.owner, caller, now_nanos / 1_000_000_000)
+                .map_err(|message| Icrc7TransferError::GenericError { error_code: Nat::from(2u64), message })?;
+        }
+        TOKEN_SUBACCOUNTS.with(|subaccounts| {
+            let mut subaccounts = subaccounts.borrow_mut();
+            match &to.subaccount {
+                Some(subaccount) => subaccounts.insert(stream_id, subaccount.clone()),
+                None => subaccounts.remove(&stream_id),
+            }
+        });
+        Ok(())
+    })?;
+    let tx = NEXT_TOKEN_TX_ID.with(|id| {
+        let mut id_mut = id.borrow_mut();
+        let curr = *id_mut;
+        *id_mut += 1;
+        curr
+    });
+    if arg.created_at_time.is_some() {
+        RECENT_TOKEN_TRANSFERS.with(|recent| recent.borrow_mut().push_back((caller, arg, tx)));
+    }
+    Ok(tx)
+}
+
+#[ic_cdk::query]
+fn icrc10_supported_standards() -> Vec<SupportedStandard> {
+    vec![
+        SupportedStandard {
+            name: "ICRC-7".to_string(),
+            url: "https://github.com/dfinity/ICRC/ICRCs/ICRC-7".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/ICRCs/ICRC-10".to_string(),
+        },
+    ]
+}
+
+// Export the candid interface
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[n; 29])
+    }
+
+    fn stream(id: u64, sender: Principal, recipient: Principal) -> Stream {
+        Stream {
+            id,
+            sender,
+            recipient,
+            sats_per_sec: 10,
+            created_at: 1_000,
+            start_time: 1_000,
+            end_time: 2_000,
+            duration_secs: 1_000,
+            total_locked: 10_000,
+            total_released: 0,
+            last_release_time: 1_000,
+            buffer: 0,
+            status: StreamStatus::Active,
+            last_claim_time: 1_000,
+            total_claimed: 0,
+            total_reclaimed: 0,
+            fee_charged: 0,
+            claim_count: 0,
+            paused_at: None,
+            total_paused_secs: 0,
+            title: None,
+            description: None,
+            tags: Vec::new(),
+            metadata: HashMap::new(),
+            public: false,
+            recipient_history: Vec::new(),
+        }
+    }
+
+    fn delivery() -> WebhookDelivery {
+        WebhookDelivery {
+            id: 1,
+            webhook_id: 1,
+            stream_id: 1,
+            event: NotificationType::StreamCreated,
+            payload: "{}".to_string(),
+            status: WebhookDeliveryStatus::InFlight,
+            attempts: 0,
+            next_attempt_at: 0,
+            in_flight_since: Some(0),
+            last_status_code: None,
+            last_error: None,
+            created_at: 0,
+            delivered_at: None,
+        }
+    }
+
+    // Answers outcalls from a queue of canned outcomes and keeps the requests it was sent
+    struct StubTransport {
+        now: std::cell::Cell<u64>,
+        outcomes: std::cell::RefCell<VecDeque<Result<u64, String>>>,
+        requests: std::cell::RefCell<Vec<outcall::CanisterHttpRequestArgument>>,
+    }
+
+    impl StubTransport {
+        fn new(now: u64, outcomes: Vec<Result<u64, String>>) -> Self {
+            StubTransport {
+                now: std::cell::Cell::new(now),
+                outcomes: std::cell::RefCell::new(outcomes.into()),
+                requests: std::cell::RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl WebhookTransport for StubTransport {
+        fn now(&self) -> u64 {
+            self.now.get()
+        }
+
+        async fn post(&self, request: outcall::CanisterHttpRequestArgument) -> Result<u64, String> {
+            self.requests.borrow_mut().push(request);
+            self.outcomes.borrow_mut().pop_front().expect("unexpected outcall")
+        }
+    }
+
+    // The stub never suspends, so a single poll drives a dispatch to completion
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        match future.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("dispatch suspended"),
+        }
+    }
+
+    fn subscribe(owner: Principal) -> Webhook {
+        let webhook = Webhook {
+            id: 1,
+            owner,
+            url: "https://hooks.example.com/satoshiflow".to_string(),
+            secret: "s3cret".to_string(),
+            events: vec![NotificationType::StreamClaimed],
+            created_at: 0,
+        };
+        WEBHOOKS.with(|webhooks| webhooks.borrow_mut().insert(webhook.id, webhook.clone()));
+        webhook
+    }
+
+    fn deliveries() -> Vec<WebhookDelivery> {
+        WEBHOOK_DELIVERIES.with(|deliveries| {
+            let mut all: Vec<WebhookDelivery> = deliveries.borrow().values().cloned().collect();
+            all.sort_by_key(|d| d.id);
+            all
+        })
+    }
+
+    fn streams(count: u64) -> Vec<Stream> {
+        (0..count).map(|id| stream(id, principal(1), principal(2))).collect()
+    }
+
+    fn page_ids(page: &StreamPage) -> Vec<u64> {
+        page.streams.iter().map(|s| s.id).collect()
+    }
+
+    #[test]
+    fn pagination_of_no_streams_is_empty() {
+        let page = paginate_streams(Vec::new(), &StreamListOptions::default());
+        assert!(page.streams.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn pagination_walks_pages_and_stops_on_an_exact_last_page() {
+        let all = streams(4);
+        let mut options = StreamListOptions { limit: Some(2), ..Default::default() };
+
+        let first = paginate_streams(all.iter().collect(), &options);
+        assert_eq!(page_ids(&first), vec![3, 2]);
+        options.cursor = first.next_cursor;
+        assert!(options.cursor.is_some());
+
+        // The last page is exactly full, so there is nothing left to point at
+        let second = paginate_streams(all.iter().collect(), &options);
+        assert_eq!(page_ids(&second), vec![1, 0]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[test]
+    fn pagination_past_the_end_returns_nothing() {
+        let all = streams(3);
+        let options = StreamListOptions {
+            descending: Some(false),
+            cursor: Some(StreamCursor { key: 2, id: 2 }),
+            ..Default::default()
+        };
+        let page = paginate_streams(all.iter().collect(), &options);
+        assert!(page.streams.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn pagination_sorts_ties_by_id_and_clamps_the_limit() {
+        let mut all = streams(3);
+        all[0].total_locked = 500;
+        let options = StreamListOptions {
+            sort_by: Some(StreamSortField::Amount),
+            descending: Some(false),
+            limit: Some(0),
+            ..Default::default()
+        };
+        let page = paginate_streams(all.iter().collect(), &options);
+        assert_eq!(page_ids(&page), vec![0]);
+        let next = StreamListOptions { cursor: page.next_cursor, ..options };
+        assert_eq!(page_ids(&paginate_streams(all.iter().collect(), &next)), vec![1]);
+    }
+
+    fn tagged_stream() -> Stream {
+        let mut s = stream(7, principal(1), principal(2));
+        s.title = Some("Monthly Salary".to_string());
+        s.description = Some("Paid to the design team".to_string());
+        s.tags = vec!["payroll".to_string(), "Design".to_string()];
+        s.metadata = HashMap::from([("team".to_string(), "design".to_string())]);
+        s
+    }
+
+    #[test]
+    fn filter_matches_tags_case_insensitively() {
+        let s = tagged_stream();
+        let any = |tags: &[&str]| StreamFilter {
+            tags_any: Some(tags.iter().map(|t| t.to_string()).collect()),
+            ..Default::default()
+        };
+        let all = |tags: &[&str]| StreamFilter {
+            tags_all: Some(tags.iter().map(|t| t.to_string()).collect()),
+            ..Default::default()
+        };
+        assert!(stream_matches_filter(&s, &any(&["grants", " DESIGN "])));
+        assert!(!stream_matches_filter(&s, &any(&["grants"])));
+        // An empty tag list places no constraint
+        assert!(stream_matches_filter(&s, &any(&[])));
+        assert!(stream_matches_filter(&s, &all(&["payroll", "design"])));
+        assert!(!stream_matches_filter(&s, &all(&["payroll", "grants"])));
+    }
+
+    #[test]
+    fn filter_matches_metadata_and_text() {
+        let s = tagged_stream();
+        let metadata = |pairs: &[(&str, &str)]| StreamFilter {
+            metadata: Some(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+            ..Default::default()
+        };
+        assert!(stream_matches_filter(&s, &metadata(&[("team", "design")])));
+        assert!(!stream_matches_filter(&s, &metadata(&[("team", "Design")])));
+        assert!(!stream_matches_filter(&s, &metadata(&[("team", "design"), ("region", "eu")])));
+
+        let text = |t: &str| StreamFilter { text: Some(t.to_string()), ..Default::default() };
+        assert!(stream_matches_filter(&s, &text("salary")));
+        assert!(stream_matches_filter(&s, &text("DESIGN TEAM")));
+        assert!(!stream_matches_filter(&s, &text("bonus")));
+    }
+
+    #[test]
+    fn filter_combinations_must_all_match() {
+        let s = tagged_stream();
+        let mut filter = StreamFilter {
+            status: Some(StreamStatus::Active),
+            tags_any: Some(vec!["payroll".to_string()]),
+            text: Some("salary".to_string()),
+            counterparty: Some(principal(2)),
+            min_amount: Some(10_000),
+            ..Default::default()
+        };
+        assert!(stream_matches_filter(&s, &filter));
+
+        filter.counterparty = Some(principal(3));
+        assert!(!stream_matches_filter(&s, &filter));
+        filter.counterparty = Some(principal(1));
+        filter.status = Some(StreamStatus::Paused);
+        assert!(!stream_matches_filter(&s, &filter));
+        filter.status = None;
+        filter.has_unclaimed_buffer = Some(true);
+        assert!(!stream_matches_filter(&s, &filter));
+    }
+
+    fn grant(owner: Principal, delegate: Principal, permissions: Vec<DelegatePermission>) -> DelegateGrant {
+        DelegateGrant {
+            owner,
+            delegate,
+            permissions,
+            expires_at: Some(5_000),
+            spend_cap: Some(1_000),
+            spent: 0,
+            granted_at: 0,
+        }
+    }
+
+    #[test]
+    fn authorize_counts_charges_against_the_spend_cap() {
+        let (owner, delegate) = (principal(1), principal(2));
+        let permissions = vec![DelegatePermission::CreateStream, DelegatePermission::TopUp];
+        DELEGATE_GRANTS.with(|grants| grants.borrow_mut().insert((owner, delegate), grant(owner, delegate, permissions)));
+
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::CreateStream, 600, 100).is_ok());
+        charge_delegate(&owner, &delegate, 600);
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::TopUp, 401, 100).is_err());
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::TopUp, 400, 100).is_ok());
+        charge_delegate(&owner, &delegate, 400);
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::TopUp, 1, 100).is_err());
+        // Actions that lock nothing are not limited by the cap
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::TopUp, 0, 100).is_ok());
+        // The owner is never limited
+        assert!(authorize_at(&owner, &owner, DelegatePermission::TopUp, 1_000_000, 100).is_ok());
+    }
+
+    #[test]
+    fn authorize_requires_an_unexpired_grant_with_the_permission() {
+        let (owner, delegate) = (principal(1), principal(2));
+        DELEGATE_GRANTS.with(|grants| {
+            grants.borrow_mut().insert((owner, delegate), grant(owner, delegate, vec![DelegatePermission::PauseResume]))
+        });
+
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::PauseResume, 0, 4_999).is_ok());
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::PauseResume, 0, 5_000).is_err());
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::Cancel, 0, 100).is_err());
+        assert!(authorize_at(&owner, &principal(3), DelegatePermission::PauseResume, 0, 100).is_err());
+        // Grants are directional
+        assert!(authorize_at(&delegate, &owner, DelegatePermission::PauseResume, 0, 100).is_err());
+    }
+
+    fn owners(members: &[u8], threshold: u32) -> StreamOwners {
+        StreamOwners {
+            owners: members.iter().map(|n| principal(*n)).collect(),
+            threshold,
+            large_top_up_threshold: 1_000,
+        }
+    }
+
+    fn owners_proposal(approvals: &[u8], action: ProposalAction) -> Proposal {
+        Proposal {
+            id: 0,
+            stream_id: 9,
+            action,
+            proposer: principal(approvals[0]),
+            approvals: approvals.iter().map(|n| principal(*n)).collect(),
+            created_at: 0,
+            expires_at: PROPOSAL_TTL_SECS,
+            status: ProposalStatus::Pending,
+        }
+    }
+
+    #[test]
+    fn multisig_proposal_waits_for_the_threshold() {
+        store(stream(9, principal(1), principal(5)));
+        let current = owners(&[1, 2, 3], 2);
+        let updated = owners(&[1, 2, 3, 4], 3);
+
+        let mut proposal = owners_proposal(&[1], ProposalAction::UpdateOwners(updated.clone()));
+        execute_proposal_if_approved(&mut proposal, &current);
+        assert_eq!(proposal.status, ProposalStatus::Pending);
+        assert!(stream_owners(9).is_none());
+
+        proposal.approvals.push(principal(3));
+        execute_proposal_if_approved(&mut proposal, &current);
+        assert_eq!(proposal.status, ProposalStatus::Executed);
+        assert_eq!(stream_owners(9).map(|o| o.owners), Some(updated.owners));
+    }
+
+    #[test]
+    fn multisig_only_counts_approvals_from_current_owners() {
+        // Owner 3 approved before being removed from the owner set
+        store(stream(9, principal(1), principal(5)));
+        let current = owners(&[1, 2], 2);
+        let mut proposal = owners_proposal(&[1, 3], ProposalAction::UpdateOwners(owners(&[1, 2], 1)));
+        execute_proposal_if_approved(&mut proposal, &current);
+        assert_eq!(proposal.status, ProposalStatus::Pending);
+
+        proposal.approvals.push(principal(2));
+        execute_proposal_if_approved(&mut proposal, &current);
+        assert_eq!(proposal.status, ProposalStatus::Executed);
+    }
+
+    #[test]
+    fn stream_owners_are_validated() {
+        let sender = principal(1);
+        let validated = validate_stream_owners(owners(&[2, 1, 2], 2), &sender).unwrap();
+        assert_eq!(validated.owners, vec![principal(1), principal(2)]);
+        assert!(validate_stream_owners(owners(&[1, 2], 0), &sender).is_err());
+        assert!(validate_stream_owners(owners(&[1, 1], 2), &sender).is_err());
+        assert!(validate_stream_owners(owners(&[], 1), &sender).is_err());
+        let too_many: Vec<u8> = (1..=MAX_STREAM_OWNERS as u8 + 1).collect();
+        assert!(validate_stream_owners(owners(&too_many, 1), &sender).is_err());
+        let mut anonymous = owners(&[1], 1);
+        anonymous.owners.push(Principal::anonymous());
+        assert!(validate_stream_owners(anonymous, &sender).is_err());
+        // The sender can never be voted out
+        assert!(validate_stream_owners(owners(&[2, 3], 1), &sender).is_err());
+    }
+
+    #[test]
+    fn owner_updates_that_drop_the_sender_fail_at_execution() {
+        store(stream(9, principal(1), principal(5)));
+        let current = owners(&[1, 2], 1);
+        let mut proposal = owners_proposal(&[2], ProposalAction::UpdateOwners(owners(&[2, 3], 1)));
+        execute_proposal_if_approved(&mut proposal, &current);
+        assert!(matches!(proposal.status, ProposalStatus::Failed(_)));
+        assert!(stream_owners(9).is_none());
+    }
+
+    fn store(stream: Stream) {
+        USER_STREAMS.with(|index| {
+            let mut index = index.borrow_mut();
+            index.entry(stream.sender).or_default().insert(stream.id);
+            index.entry(stream.recipient).or_default().insert(stream.id);
+        });
+        STREAMS.with(|streams| streams.borrow_mut().insert(stream.id, stream));
+    }
+
+    fn account(owner: Principal, subaccount: Option<[u8; 32]>) -> Account {
+        Account { owner, subaccount: subaccount.map(|s| s.to_vec()) }
+    }
+
+    fn token_transfer(to: Account, from_subaccount: Option<[u8; 32]>, created_at_time: Option<u64>) -> Icrc7TransferArg {
+        Icrc7TransferArg {
+            from_subaccount: from_subaccount.map(|s| s.to_vec()),
+            to,
+            token_id: Nat::from(0u64),
+            memo: None,
+            created_at_time,
+        }
+    }
+
+    #[test]
+    fn icrc7_tokens_belong_to_the_recipient_account() {
+        let (sender, recipient) = (principal(1), principal(2));
+        store(stream(0, sender, recipient));
+        store(stream(1, sender, recipient));
+        let mut public = stream(2, sender, recipient);
+        public.public = true;
+        store(public);
+
+        let everything = |_: &Stream| true;
+        assert_eq!(tokens_of_account(&account(recipient, None), None, 100, everything), vec![0, 1, 2]);
+        // Zero subaccounts are the default account
+        assert_eq!(tokens_of_account(&account(recipient, Some([0; 32])), None, 100, everything), vec![0, 1, 2]);
+        assert_eq!(tokens_of_account(&account(recipient, None), Some(0), 1, everything), vec![1]);
+        assert!(tokens_of_account(&account(sender, None), None, 100, everything).is_empty());
+        // Callers who may not view a stream do not see its token
+        assert_eq!(tokens_of_account(&account(recipient, None), None, 100, |s| s.public), vec![2]);
+    }
+
+    #[test]
+    fn icrc7_transfer_moves_the_token_between_subaccounts() {
+        let recipient = principal(2);
+        store(stream(0, principal(1), recipient));
+        let now = 10 * ICRC7_TX_WINDOW_NANOS;
+        let to = account(recipient, Some([7; 32]));
+
+        assert!(matches!(transfer_token(recipient, token_transfer(to.clone(), None, Some(now)), now), Ok(0)));
+        assert_eq!(STREAMS.with(|streams| token_owner(&streams.borrow()[&0])), to);
+        assert!(tokens_of_account(&account(recipient, None), None, 100, |_| true).is_empty());
+        assert_eq!(tokens_of_account(&to, None, 100, |_| true), vec![0]);
+
+        // Replaying the same transfer inside the window is rejected
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(to.clone(), None, Some(now)), now),
+            Err(Icrc7TransferError::Duplicate { .. })
+        ));
+        // The token now sits in the subaccount
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(account(recipient, None), None, None), now),
+            Err(Icrc7TransferError::Unauthorized)
+        ));
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(account(recipient, None), Some([7; 32]), None), now),
+            Ok(1)
+        ));
+    }
+
+    #[test]
+    fn icrc7_transfer_to_another_principal_hands_over_the_stream() {
+        let (sender, recipient, buyer) = (principal(1), principal(2), principal(3));
+        let mut s = stream(0, sender, recipient);
+        s.total_released = 300;
+        s.buffer = 300;
+        store(s);
+        let now = 10 * ICRC7_TX_WINDOW_NANOS;
+        let to = account(buyer, Some([7; 32]));
+
+        assert!(matches!(transfer_token(recipient, token_transfer(to.clone(), None, Some(now)), now), Ok(0)));
+        let s = STREAMS.with(|streams| streams.borrow()[&0].clone());
+        assert_eq!(s.recipient, buyer);
+        assert_eq!(token_owner(&s), to);
+        // The accrued buffer was paid out to the seller before the handover
+        assert_eq!((s.buffer, s.total_claimed), (0, 300));
+        assert_eq!((s.recipient_history[0].from, s.recipient_history[0].to), (recipient, buyer));
+        assert_eq!(s.recipient_history[0].settled, 300);
+        assert!(tokens_of_account(&account(recipient, None), None, 100, |_| true).is_empty());
+        assert_eq!(tokens_of_account(&to, None, 100, |_| true), vec![0]);
+        let audit = AUDIT_LOG.with(|log| log.borrow()[&0].clone());
+        assert_eq!((audit[0].actor, audit[0].timestamp), (recipient, now / 1_000_000_000));
+
+        // Only the new owner can move it on
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(account(principal(4), None), None, None), now),
+            Err(Icrc7TransferError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn icrc7_transfer_rejects_invalid_requests() {
+        let (sender, recipient) = (principal(1), principal(2));
+        store(stream(0, sender, recipient));
+        let now = 10 * ICRC7_TX_WINDOW_NANOS;
+        let elsewhere = account(principal(3), None);
+
+        assert!(matches!(
+            transfer_token(sender, token_transfer(elsewhere.clone(), None, None), now),
+            Err(Icrc7TransferError::Unauthorized)
+        ));
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(account(recipient, Some([0; 32])), None, None), now),
+            Err(Icrc7TransferError::InvalidRecipient)
+        ));
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(account(Principal::anonymous(), None), None, None), now),
+            Err(Icrc7TransferError::InvalidRecipient)
+        ));
+        let too_old = now - ICRC7_TX_WINDOW_NANOS - ICRC7_PERMITTED_DRIFT_NANOS - 1;
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(elsewhere.clone(), None, Some(too_old)), now),
+            Err(Icrc7TransferError::TooOld)
+        ));
+        let future = now + ICRC7_PERMITTED_DRIFT_NANOS + 1;
+        assert!(matches!(
+            transfer_token(recipient, token_transfer(elsewhere.clone(), None, Some(future)), now),
+            Err(Icrc7TransferError::CreatedInFuture { .. })
+        ));
+        let mut long_memo = token_transfer(elsewhere.clone(), None, None);
+        long_memo.memo = Some(vec![0; ICRC7_MAX_MEMO_SIZE + 1]);
+        assert!(matches!(transfer_token(recipient, long_memo, now), Err(Icrc7TransferError::GenericError { .. })));
+        let mut missing = token_transfer(elsewhere, None, None);
+        missing.token_id = Nat::from(99u64);
+        assert!(matches!(transfer_token(recipient, missing, now), Err(Icrc7TransferError::NonExistingTokenId)));
+    }
+
+    #[test]
+    fn sender_transfer_hands_over_the_owner_seat() {
+        let updated = replace_owner(&owners(&[1, 2, 3], 2), principal(1), principal(4)).unwrap();
+        assert_eq!(updated.owners, vec![principal(2), principal(3), principal(4)]);
+        assert_eq!(updated.threshold, 2);
+        // Handing the seat to an existing owner would leave too few owners for the threshold
+        assert!(replace_owner(&owners(&[1, 2], 2), principal(1), principal(2)).is_err());
+    }
+
+    #[test]
+    fn stale_sender_transfers_are_dropped() {
+        let pending = |stream_id, expires_at| PendingSenderTransfer {
+            stream_id,
+            from: principal(1),
+            to: principal(2),
+            proposed_at: 0,
+            expires_at,
+        };
+        PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow_mut().extend([(0, pending(0, 100)), (1, pending(1, 101))]));
+        prune_expired_sender_transfers(100);
+        let left: Vec<u64> = PENDING_SENDER_TRANSFERS.with(|transfers| transfers.borrow().keys().copied().collect());
+        assert_eq!(left, vec![1]);
+    }
+
+    fn set_quotas(creates_per_hour: u32, max_active_streams: u32) {
+        CONFIG.with(|config| {
+            let quotas = &mut config.borrow_mut().quotas;
+            quotas.creates_per_hour = creates_per_hour;
+            quotas.max_active_streams = max_active_streams;
+        });
+    }
+
+    #[test]
+    fn quota_rejects_amounts_below_the_minimum() {
+        let minimum = DEFAULT_QUOTAS.min_stream_amount;
+        assert!(matches!(
+            check_stream_quotas(principal(1), minimum - 1, 0),
+            Err(QuotaError::AmountBelowMinimum { minimum: m }) if m == minimum
+        ));
+        assert!(check_stream_quotas(principal(1), minimum, 0).is_ok());
+    }
+
+    #[test]
+    fn quota_counts_only_active_and_paused_streams_funded_by_the_sender() {
+        set_quotas(10, 2);
+        let sender = principal(1);
+        store(stream(0, sender, principal(2)));
+        let mut completed = stream(1, sender, principal(2));
+        completed.status = StreamStatus::Completed;
+        store(completed);
+        // Receiving a stream does not use up the sender-side quota
+        store(stream(2, principal(3), sender));
+        assert!(check_stream_quotas(sender, 10_000, 0).is_ok());
+
+        let mut paused = stream(3, sender, principal(2));
+        paused.status = StreamStatus::Paused;
+        store(paused);
+        assert!(matches!(
+            check_stream_quotas(sender, 10_000, 0),
+            Err(QuotaError::TooManyActiveStreams { limit: 2 })
+        ));
+    }
+
+    #[test]
+    fn quota_limits_creates_per_rolling_hour() {
+        set_quotas(2, 100);
+        let sender = principal(1);
+        RECENT_CREATES.with(|recent| recent.borrow_mut().insert(sender, VecDeque::from([100, 200])));
+
+        assert!(matches!(
+            check_stream_quotas(sender, 10_000, 300),
+            Err(QuotaError::CreateRateExceeded { limit: 2, retry_after_secs: 3_400 })
+        ));
+        // Once the oldest create leaves the window there is room again
+        assert!(check_stream_quotas(sender, 10_000, 100 + HOUR_SECS).is_ok());
+        assert_eq!(RECENT_CREATES.with(|recent| recent.borrow()[&sender].len()), 1);
+        // Other principals are unaffected
+        assert!(check_stream_quotas(principal(2), 10_000, 300).is_ok());
+    }
+
+    #[test]
+    fn recording_a_create_prunes_expired_windows_of_all_senders() {
+        RECENT_CREATES.with(|recent| {
+            let mut recent = recent.borrow_mut();
+            recent.insert(principal(1), VecDeque::from([100]));
+            recent.insert(principal(2), VecDeque::from([100, HOUR_SECS + 50]));
+        });
+        record_recent_create(principal(3), HOUR_SECS + 100);
+        RECENT_CREATES.with(|recent| {
+            let recent = recent.borrow();
+            assert!(!recent.contains_key(&principal(1)));
+            assert_eq!(recent[&principal(2)], VecDeque::from([HOUR_SECS + 50]));
+            assert_eq!(recent[&principal(3)], VecDeque::from([HOUR_SECS + 100]));
+        });
+    }
+
+    #[test]
+    fn reclaim_waits_for_the_timeout_after_the_later_of_end_and_last_claim() {
+        let mut s = stream(0, principal(1), principal(2));
+        s.last_claim_time = 2_500;
+        assert_eq!(reclaimable_at(&s, 100), 2_600);
+        s.end_time = u64::MAX;
+        assert_eq!(reclaimable_at(&s, DEFAULT_RECLAIM_TIMEOUT_SECS), u64::MAX);
+    }
+
+    #[test]
+    fn reclaim_timeout_updates_are_range_checked() {
+        let update = |timeout| ConfigUpdate {
+            ledger_canister_id: None,
+            fee_percent: None,
+            reclaim_timeout_secs: Some(timeout),
+            limits: None,
+            quotas: None,
+        };
+        assert!(apply_config_update(update(0)).is_err());
+        assert!(apply_config_update(update(u64::MAX)).is_err());
+        assert_eq!(apply_config_update(update(MIN_RECLAIM_TIMEOUT_SECS)).unwrap().reclaim_timeout_secs, MIN_RECLAIM_TIMEOUT_SECS);
+    }
+
+    #[test]
+    fn halting_claims_also_blocks_cancel_while_accrual_is_frozen() {
+        HALT_STATE.with(|state| state.borrow_mut().halted = vec![HaltableOperation::Claim]);
+        sync_accrual_freeze(100);
+        let state = HALT_STATE.with(|state| state.borrow().clone());
+        assert!(is_halted(&state, HaltableOperation::Claim));
+        assert!(is_halted(&state, HaltableOperation::Cancel));
+        assert!(!is_halted(&state, HaltableOperation::TopUp));
+        assert!(!is_halted(&state, HaltableOperation::CreateStream));
+
+        HALT_STATE.with(|state| state.borrow_mut().halted.clear());
+        sync_accrual_freeze(200);
+        let state = HALT_STATE.with(|state| state.borrow().clone());
+        assert!(!is_halted(&state, HaltableOperation::Cancel));
+    }
+
+    #[test]
+    fn user_data_needs_a_view_grant_not_just_any_grant() {
+        let (owner, delegate) = (principal(1), principal(2));
+        assert!(can_view_user_at(&owner, &owner, 100));
+        let insert = |permissions| {
+            DELEGATE_GRANTS.with(|grants| grants.borrow_mut().insert((owner, delegate), grant(owner, delegate, permissions)))
+        };
+        insert(vec![DelegatePermission::Claim]);
+        assert!(authorize_at(&owner, &delegate, DelegatePermission::ViewStreams, 0, 100).is_err());
+        insert(vec![DelegatePermission::ViewStreams]);
+        assert!(can_view_user_at(&owner, &delegate, 100));
+    }
+
+    #[test]
+    fn reassigning_the_recipient_settles_the_buffer_and_moves_received_stats() {
+        let (sender, old, new) = (principal(1), principal(2), principal(3));
+        let mut s = stream(4, sender, old);
+        s.total_released = 600;
+        s.buffer = 600;
+        store(s.clone());
+        update_stats_on_create(sender, old, s.total_locked, s.duration_secs, s.created_at);
+
+        assert_eq!(reassign_recipient(&mut s, new, sender, 1_500), Ok(600));
+
+        assert_eq!(s.recipient, new);
+        assert_eq!((s.buffer, s.total_claimed), (0, 600));
+        assert_eq!(s.recipient_history.len(), 1);
+        assert_eq!((s.recipient_history[0].from, s.recipient_history[0].settled), (old, 600));
+        USER_STREAMS.with(|index| {
+            let index = index.borrow();
+            assert!(!index[&old].contains(&4));
+            assert!(index[&new].contains(&4));
+        });
+        USER_STATS.with(|stats| {
+            let stats = stats.borrow();
+            assert_eq!((stats[&old].streams_received, stats[&old].total_received), (0, 600));
+            assert_eq!(stats[&new].streams_received, 1);
+        });
+        COUNTERPARTIES.with(|counterparties| {
+            let counterparties = counterparties.borrow();
+            assert_eq!(counterparties[&sender][&old].sent, 600);
+            assert_eq!(counterparties[&sender][&old].streams, 0);
+            assert_eq!((counterparties[&sender][&new].sent, counterparties[&sender][&new].streams), (9_400, 1));
+            assert_eq!(counterparties[&new][&sender].received, 9_400);
+        });
+        HOURLY_BUCKETS.with(|buckets| {
+            let buckets = buckets.borrow();
+            let old_bucket = &buckets[&StatsScope::User(old)][&0];
+            assert_eq!((old_bucket.volume_received, old_bucket.streams_received), (600, 0));
+            let new_bucket = &buckets[&StatsScope::User(new)][&0];
+            assert_eq!((new_bucket.volume_received, new_bucket.streams_received), (9_400, 1));
+        });
+        let audit = AUDIT_LOG.with(|log| log.borrow()[&4].clone());
+        assert_eq!((audit[0].actor, audit[0].timestamp), (sender, 1_500));
+    }
+
+    #[test]
+    fn public_leaderboard_only_counts_public_streams() {
+        let (alice, bob, carol) = (principal(1), principal(2), principal(3));
+        let mut shown = stream(0, alice, bob);
+        let mut hidden = stream(1, alice, bob);
+        hidden.total_locked = 50_000;
+        set_visibility(&mut hidden, false);
+        set_visibility(&mut shown, true);
+        let volumes = |kind| {
+            public_leaderboard(kind, 10, 0, 4_000).iter().map(|e| (e.user, e.volume, e.streams)).collect::<Vec<_>>()
+        };
+        assert_eq!(volumes(LeaderboardKind::Senders), vec![(alice, 10_000, 1)]);
+        assert_eq!(volumes(LeaderboardKind::Recipients), vec![(bob, 10_000, 1)]);
+        // Ranges are aligned to the creation hour of each stream
+        assert!(public_leaderboard(LeaderboardKind::Senders, 10, 3_600, 4_000).is_empty());
+
+        // A recipient transfer carries the public volume over to the new recipient
+        reassign_recipient(&mut shown, carol, bob, 1_500).unwrap();
+        assert_eq!(volumes(LeaderboardKind::Recipients), vec![(carol, 10_000, 1)]);
+
+        set_visibility(&mut shown, false);
+        assert!(volumes(LeaderboardKind::Senders).is_empty());
+        assert!(PUBLIC_VOLUME.with(|volume| volume.borrow().is_empty()));
+    }
+
+    #[test]
+    fn dispatch_posts_signed_deliveries_and_marks_them_delivered() {
+        let webhook = subscribe(principal(1));
+        enqueue_webhook_deliveries(principal(1), 7, &NotificationType::StreamClaimed, "Recipient claimed 5 sats", 100);
+        let transport = StubTransport::new(120, vec![Ok(204)]);
+
+        block_on(dispatch_webhooks_with(&transport));
+
+        let requests = transport.requests.borrow();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(request.url, webhook.url);
+        assert_eq!(request.method, outcall::HttpMethod::POST);
+        let payload = String::from_utf8(request.body.clone().unwrap()).unwrap();
+        let header = |name: &str| request.headers.iter().find(|h| h.name == name).map(|h| h.value.clone());
+        let delivery = &deliveries()[0];
+        assert_eq!(payload, delivery.payload);
+        assert_eq!(header("X-SatoshiFlow-Delivery"), Some(delivery.id.to_string()));
+        assert_eq!(header("X-SatoshiFlow-Timestamp"), Some("120".to_string()));
+        let signature = webhook_signature(&webhook.secret, "120", &payload);
+        assert_eq!(header("X-SatoshiFlow-Signature"), Some(format!("sha256={}", signature)));
+
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Delivered);
+        assert_eq!((delivery.attempts, delivery.last_status_code, delivery.delivered_at), (1, Some(204), Some(120)));
+    }
+
+    #[test]
+    fn dispatch_backs_off_after_failures_and_retries_when_due() {
+        subscribe(principal(1));
+        enqueue_webhook_deliveries(principal(1), 7, &NotificationType::StreamClaimed, "Recipient claimed 5 sats", 100);
+        // Events the webhook did not subscribe to are not queued
+        enqueue_webhook_deliveries(principal(1), 7, &NotificationType::StreamTopUp, "Stream topped up", 100);
+        let transport = StubTransport::new(100, vec![Ok(500), Err("connection refused".to_string()), Ok(200)]);
+
+        block_on(dispatch_webhooks_with(&transport));
+        let delivery = &deliveries()[0];
+        assert_eq!(deliveries().len(), 1);
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Pending);
+        assert_eq!(delivery.last_error.as_deref(), Some("Endpoint responded with status 500"));
+        assert_eq!(delivery.next_attempt_at, 100 + webhook_backoff_secs(1));
+
+        // Nothing is sent before the backoff elapses
+        transport.now.set(delivery.next_attempt_at - 1);
+        block_on(dispatch_webhooks_with(&transport));
+        assert_eq!(transport.requests.borrow().len(), 1);
+
+        let retry_at = delivery.next_attempt_at;
+        transport.now.set(retry_at);
+        block_on(dispatch_webhooks_with(&transport));
+        let delivery = &deliveries()[0];
+        assert_eq!((delivery.attempts, delivery.last_status_code), (2, None));
+        assert_eq!(delivery.last_error.as_deref(), Some("connection refused"));
+        assert_eq!(delivery.next_attempt_at, retry_at + webhook_backoff_secs(2));
+
+        transport.now.set(delivery.next_attempt_at);
+        block_on(dispatch_webhooks_with(&transport));
+        assert_eq!(deliveries()[0].status, WebhookDeliveryStatus::Delivered);
+        assert_eq!(transport.requests.borrow().len(), 3);
+    }
+
+    #[test]
+    fn pruning_drops_expired_then_read_then_oldest_notifications() {
+        RETENTION_POLICY.with(|policy| *policy.borrow_mut() = RetentionPolicy { max_per_user: 3, max_age_secs: 1_000 });
+        let user = principal(1);
+        for timestamp in [100, 2_000, 2_100, 2_200, 2_300, 2_400] {
+            create_notification(user, 0, NotificationType::StreamClaimed, timestamp.to_string(), timestamp);
+        }
+        // Two of the newer notifications are read, so they go before the older unread ones
+        NOTIFICATIONS.with(|notifications| {
+            for notification in notifications.borrow_mut().values_mut() {
+                notification.read = matches!(notification.timestamp, 2_200 | 2_300);
+            }
+        });
+
+        prune_notifications_at(2_500);
+
+        let mut left: Vec<u64> = NOTIFICATIONS.with(|n| n.borrow().values().map(|n| n.timestamp).collect());
+        left.sort();
+        assert_eq!(left, vec![2_000, 2_100, 2_400]);
+        assert_eq!(USER_NOTIFICATIONS.with(|index| index.borrow()[&user].len()), 3);
+        let metrics = NOTIFICATION_METRICS.with(|metrics| metrics.borrow().clone());
+        assert_eq!((metrics.evicted_by_age, metrics.evicted_by_cap, metrics.last_prune_time), (1, 2, 2_500));
+
+        // Once nothing read is left, the oldest unread notifications go
+        RETENTION_POLICY.with(|policy| policy.borrow_mut().max_per_user = 1);
+        prune_notifications_at(2_500);
+        let left: Vec<u64> = NOTIFICATIONS.with(|n| n.borrow().values().map(|n| n.timestamp).collect());
+        assert_eq!(left, vec![2_400]);
+    }
+
+    #[test]
+    fn analytics_for_an_unclaimed_stream_has_no_claim_interval() {
+        let mut s = stream(0, principal(1), principal(2));
+        s.total_released = 3_000;
+        s.buffer = 3_000;
+        s.last_release_time = 1_300;
+
+        let analytics = stream_analytics(&s, 1_300);
+        assert_eq!(analytics.completion_percent, 30.0);
+        assert_eq!((analytics.claim_count, analytics.average_claim_interval_secs), (0, None));
+        assert_eq!((analytics.claimed, analytics.unclaimed), (0, 3_000));
+        assert_eq!((analytics.projected_end_time, analytics.remaining_runway_secs), (Some(2_000), 700));
+    }
+
+    #[test]
+    fn analytics_for_a_completed_stream_has_no_runway_left() {
+        let mut s = stream(0, principal(1), principal(2));
+        s.status = StreamStatus::Completed;
+        s.total_released = 10_000;
+        s.total_claimed = 10_000;
+        s.last_release_time = 2_000;
+        s.last_claim_time = 2_000;
+        s.claim_count = 4;
+        s.total_paused_secs = 120;
+
+        let analytics = stream_analytics(&s, 5_000);
+        assert_eq!(analytics.completion_percent, 100.0);
+        assert_eq!(analytics.average_claim_interval_secs, Some(250));
+        assert_eq!((analytics.claimed, analytics.unclaimed), (10_000, 0));
+        assert_eq!(analytics.time_paused_secs, 120);
+        assert_eq!((analytics.projected_end_time, analytics.remaining_runway_secs), (None, 0));
+    }
+
+    #[test]
+    fn webhook_signature_is_hex_hmac_of_timestamp_and_payload() {
+        assert_eq!(
+            webhook_signature("whsec_test", "1700000000", r#"{"event":"StreamCreated"}"#),
+            "4522f7e3d132cdb3cad338f63c8dd57f245945f82aa8c13dc47e5072b44342a8"
+        );
+        assert_ne!(
+            webhook_signature("whsec_test", "1700000001", r#"{"event":"StreamCreated"}"#),
+            webhook_signature("whsec_test", "1700000000", r#"{"event":"StreamCreated"}"#)
+        );
+    }
+
+    #[test]
+    fn webhook_backoff_doubles_until_attempts_run_out() {
+        let schedule: Vec<u64> = (1..MAX_WEBHOOK_ATTEMPTS).map(webhook_backoff_secs).collect();
+        assert_eq!(schedule, vec![30, 60, 120, 240, 480]);
+
+        let mut d = delivery();
+        for attempt in 1..MAX_WEBHOOK_ATTEMPTS {
+            record_webhook_attempt(&mut d, Ok(500), 1_000);
+            assert_eq!(d.status, WebhookDeliveryStatus::Pending);
+            assert_eq!(d.next_attempt_at, 1_000 + webhook_backoff_secs(attempt));
+            assert_eq!(d.last_status_code, Some(500));
+        }
+        record_webhook_attempt(&mut d, Err("unreachable".to_string()), 2_000);
+        assert_eq!(d.status, WebhookDeliveryStatus::Failed);
+        assert_eq!(d.attempts, MAX_WEBHOOK_ATTEMPTS);
+    }
+
+    #[test]
+    fn webhook_success_marks_delivered() {
+        let mut d = delivery();
+        record_webhook_attempt(&mut d, Ok(204), 50);
+        assert_eq!(d.status, WebhookDeliveryStatus::Delivered);
+        assert_eq!(d.delivered_at, Some(50));
+        assert_eq!(d.in_flight_since, None);
+    }
+
+    #[test]
+    fn stuck_in_flight_deliveries_are_requeued_after_timeout() {
+        let mut deliveries = HashMap::from([(1, delivery())]);
+        requeue_stuck_webhook_deliveries(&mut deliveries, WEBHOOK_IN_FLIGHT_TIMEOUT_SECS - 1);
+        assert_eq!(deliveries[&1].status, WebhookDeliveryStatus::InFlight);
+
+        requeue_stuck_webhook_deliveries(&mut deliveries, WEBHOOK_IN_FLIGHT_TIMEOUT_SECS);
+        assert_eq!(deliveries[&1].status, WebhookDeliveryStatus::Pending);
+        assert_eq!(deliveries[&1].attempts, 1);
+        assert_eq!(deliveries[&1].in_flight_since, None);
+    }
+
+    #[test]
+    fn transform_keeps_only_the_status() {
+        let response = transform_webhook_response(outcall::TransformArgs {
+            response: outcall::HttpResponse {
+                status: Nat::from(202u32),
+                headers: vec![outcall::HttpHeader { name: "Date".to_string(), value: "now".to_string() }],
+                body: b"accepted".to_vec(),
+            },
+            context: Vec::new(),
+        });
+        assert_eq!(response.status, Nat::from(202u32));
+        assert!(response.headers.is_empty());
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn thaw_extends_by_the_full_freeze_for_streams_running_before_it() {
+        let mut s = stream(1, principal(1), principal(2));
+        s.last_release_time = 1_195; // Last heartbeat before the freeze at 1_200
+        thaw_stream(&mut s, 1_200, 1_500);
+        assert_eq!(s.end_time, 2_300);
+        // The 5 seconds owed from before the freeze still accrue on the next heartbeat
+        assert_eq!(s.last_release_time, 1_495);
+    }
+
+    #[test]
+    fn thaw_only_extends_by_time_frozen_since_a_change_during_the_freeze() {
+        // Created during the freeze
+        let mut created = stream(1, principal(1), principal(2));
+        created.start_time = 1_400;
+        created.last_release_time = 1_400;
+        created.end_time = 2_400;
+        thaw_stream(&mut created, 1_200, 1_500);
+        assert_eq!(created.end_time, 2_500);
+        assert_eq!(created.last_release_time, 1_500);
+
+        // Resumed or re-rated during the freeze, which resets the release clock
+        let mut resumed = stream(2, principal(1), principal(2));
+        resumed.last_release_time = 1_450;
+        thaw_stream(&mut resumed, 1_200, 1_500);
+        assert_eq!(resumed.end_time, 2_050);
+        assert_eq!(resumed.last_release_time, 1_500);
+    }
+
+    #[test]
+    fn recomputed_average_duration_ignores_end_time_changes() {
+        let mut extended = stream(0, principal(1), principal(2));
+        thaw_stream(&mut extended, 1_000, 1_600);
+        let mut shorter = stream(1, principal(1), principal(2));
+        shorter.duration_secs = 3_000;
+        shorter.end_time = 1_500;
+        STREAMS.with(|streams| streams.borrow_mut().extend([(0, extended), (1, shorter)]));
+
+        assert_eq!(recompute_stats_from_streams().average_stream_duration, 2_000);
+    }
+
+    #[test]
+    fn webhook_urls_require_https_outside_local_hosts() {
+        assert!(validate_webhook_url("https://example.com/hook").is_ok());
+        assert!(validate_webhook_url("http://example.com/hook").is_err());
+        assert!(validate_webhook_url("http://localhost.example.com").is_err());
+        // Tests build with the local allowance
+        assert!(validate_webhook_url("http://localhost:8080/hook").is_ok());
+        assert!(validate_webhook_url("http://127.0.0.1").is_ok());
+    }
+}
 
 
 
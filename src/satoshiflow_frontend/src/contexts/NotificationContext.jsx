@@ -1,6 +1,6 @@
 import React, { createContext, useContext, useState, useEffect } from 'react';
-import { satoshiflow_backend } from 'declarations/satoshiflow_backend';
 import { useAuth } from './AuthContext';
+import { getAuthenticatedActor } from '../utils/getBackendActor';
 
 const NotificationContext = createContext();
 
@@ -58,7 +58,7 @@ export const NotificationProvider = ({ children }) => {
   const [unreadCount, setUnreadCount] = useState(0);
   const [loading, setLoading] = useState(false);
   const [previousNotificationCount, setPreviousNotificationCount] = useState(0);
-  const { user } = useAuth();
+  const { user, walletType } = useAuth();
 
   useEffect(() => {
     if (user) {
@@ -72,7 +72,7 @@ export const NotificationProvider = ({ children }) => {
       setUnreadCount(0);
       setPreviousNotificationCount(0);
     }
-  }, [user]);
+  }, [user, walletType]);
 
   // Play notification sound when new notifications arrive
   useEffect(() => {
@@ -113,8 +113,10 @@ export const NotificationProvider = ({ children }) => {
       setLoading(true);
       console.log('Fetching notifications for user:', principalToText(user));
       
-      // Fetch all notifications from backend
-      const rawNotifications = await satoshiflow_backend.get_notifications();
+      // Notifications are per caller, so read them as the signed-in user
+      const backend = await getAuthenticatedActor(walletType);
+      const page = await backend.get_notifications([], []);
+      const rawNotifications = page.notifications;
       console.log('Raw notifications received:', rawNotifications);
       
       // Convert BigInt values and filter for current user
@@ -143,7 +145,9 @@ export const NotificationProvider = ({ children }) => {
       console.log('Filtered user notifications:', userNotifications);
       
       setNotifications(userNotifications);
-      setUnreadCount(userNotifications.filter(n => !n.read).length);
+      // The page may not contain every unread notification, so ask the backend
+      const unread = await backend.unread_count();
+      setUnreadCount(Number(unread));
       
     } catch (error) {
       console.error('Failed to fetch notifications:', error);
@@ -166,7 +170,8 @@ export const NotificationProvider = ({ children }) => {
       console.log('Marking notification as read:', notificationId);
       
       // Call backend to mark as read
-      const success = await satoshiflow_backend.mark_notification_read(notificationId);
+      const backend = await getAuthenticatedActor(walletType);
+      const success = await backend.mark_notification_read(notificationId);
       
       if (success) {
         // Update local state
@@ -192,13 +197,8 @@ export const NotificationProvider = ({ children }) => {
       setLoading(true);
       console.log('Marking all notifications as read');
       
-      // Mark each unread notification as read
-      const unreadNotifications = notifications.filter(n => !n.read);
-      const markPromises = unreadNotifications.map(notification => 
-        satoshiflow_backend.mark_notification_read(notification.id)
-      );
-      
-      await Promise.all(markPromises);
+      const backend = await getAuthenticatedActor(walletType);
+      await backend.mark_all_notifications_read();
       
       // Update local state
       setNotifications(prev => 
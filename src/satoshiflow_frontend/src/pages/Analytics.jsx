@@ -26,8 +26,9 @@ import {
   Pie,
   Cell
 } from 'recharts';
-import { satoshiflow_backend } from 'declarations/satoshiflow_backend';
 import { useAuth } from '../contexts/AuthContext';
+import { listAllStreamsForUser } from '../utils/listAllStreams';
+import { getAuthenticatedActor } from '../utils/getBackendActor';
 import StatCard from '../components/StatCard';
 import { format, subDays, startOfDay, endOfDay } from 'date-fns';
 
@@ -87,7 +88,7 @@ const Analytics = () => {
   const [statusData, setStatusData] = useState([]);
   const [loading, setLoading] = useState(true);
   
-  const { user } = useAuth();
+  const { user, walletType } = useAuth();
 
   useEffect(() => {
     if (user) {
@@ -124,7 +125,7 @@ const Analytics = () => {
       
       // Fetch user streams with proper error handling
       console.log('Calling list_streams_for_user...');
-      const rawUserStreams = await satoshiflow_backend.list_streams_for_user(user);
+      const rawUserStreams = await listAllStreamsForUser(user, walletType);
       console.log('Raw user streams received:', rawUserStreams);
       console.log('Number of streams:', rawUserStreams ? rawUserStreams.length : 'undefined');
       
@@ -203,13 +204,17 @@ const Analytics = () => {
         totalVolume: totalSent + totalReceived
       });
       
+      // Incoming/outgoing totals are tracked by the backend, including claims and top ups
+      const backend = await getAuthenticatedActor(walletType);
+      const myStats = deepBigIntToNumber(await backend.get_my_stats());
+      
       setStats({
-        totalSent,
-        totalReceived,
+        totalSent: myStats.total_sent,
+        totalReceived: myStats.total_received,
         activeStreams,
         completedStreams,
-        totalVolume: totalSent + totalReceived,
-        avgStreamSize: sentStreams.length > 0 ? totalSent / sentStreams.length : 0,
+        totalVolume: myStats.total_sent + myStats.total_received,
+        avgStreamSize: myStats.avg_stream_size,
       });
 
       // Generate chart data
@@ -218,36 +223,32 @@ const Analytics = () => {
       
       console.log('Generating chart data for', days, 'days');
       
+      // Daily buckets are maintained by the backend, so charts no longer depend on stream snapshots
+      const rangeStart = startOfDay(subDays(new Date(), days - 1));
+      const rangeEnd = endOfDay(new Date());
+      const buckets = await backend.get_stats_timeseries(
+        { User: user },
+        { Daily: null },
+        BigInt(Math.floor(rangeStart.getTime() / 1000)),
+        BigInt(Math.floor(rangeEnd.getTime() / 1000) + 1)
+      );
+      const bucketsByDay = {};
+      buckets.map(bucket => deepBigIntToNumber(bucket)).forEach(bucket => {
+        bucketsByDay[format(new Date(bucket.bucket_start * 1000), 'yyyy-MM-dd')] = bucket;
+      });
+      
       for (let i = days - 1; i >= 0; i--) {
         const date = subDays(new Date(), i);
-        const dayStart = startOfDay(date);
-        const dayEnd = endOfDay(date);
-        
-        const dayStreams = userStreams.filter(stream => {
-          try {
-            const streamTime = Number(stream.start_time || 0);
-            const streamDate = new Date(streamTime * 1000);
-            return streamDate >= dayStart && streamDate <= dayEnd;
-          } catch (error) {
-            console.warn('Error processing stream date:', stream, error);
-            return false;
-          }
-        });
-        
-        const sent = dayStreams
-          .filter(s => principalToText(s.sender) === userPrincipalText)
-          .reduce((sum, s) => sum + Number(s.total_locked || 0), 0);
-        
-        const received = dayStreams
-          .filter(s => principalToText(s.recipient) === userPrincipalText)
-          .reduce((sum, s) => sum + Number(s.total_released || 0), 0);
+        const bucket = bucketsByDay[format(date, 'yyyy-MM-dd')];
+        const sent = bucket ? bucket.volume_locked : 0;
+        const received = bucket ? bucket.volume_claimed : 0;
         
         chartData.push({
           date: format(date, 'MMM dd'),
           sent,
           received,
           total: sent + received,
-          count: dayStreams.length,
+          count: bucket ? bucket.streams_created : 0,
         });
       }
       
@@ -14,7 +14,8 @@ import { Principal } from '@dfinity/principal';
 import { satoshiflow_backend } from 'declarations/satoshiflow_backend';
 import { useAuth } from '../contexts/AuthContext';
 import { useNotifications } from '../contexts/NotificationContext';
-import { getBackendActor } from '../utils/getBackendActor';
+import { getAuthenticatedActor } from '../utils/getBackendActor';
+import { describeQuotaError } from '../utils/quotaError';
 
 // Utility: Deeply convert all BigInt fields to Number
 function deepBigIntToNumber(obj, seen = new Set()) {
@@ -183,35 +184,24 @@ const CreateStream = () => {
         throw new Error('Values must be positive numbers');
       }
 
-      // Get the authenticated identity
-      let identity = null;
-      if (walletType === 'plug' && window.ic?.plug) {
-        await window.ic.plug.createAgent();
-        identity = window.ic.plug.agent.identity;
-      } else if (walletType === 'ii') {
-        const { AuthClient } = await import('@dfinity/auth-client');
-        const authClient = await AuthClient.create();
-        identity = authClient.getIdentity();
-      }
-      console.log('Using identity:', identity);
-      const backend = getBackendActor(identity);
+      const backend = await getAuthenticatedActor(walletType);
       console.log('Backend actor:', backend);
 
       // Create stream
-      let streamId;
+      let result;
       const tagsArray = formData.tags ? formData.tags.split(',').map(t => t.trim()).filter(Boolean) : [];
       const safeTitle = formData.title && formData.title.trim() ? formData.title.trim() : null;
       const safeDescription = formData.description && formData.description.trim() ? formData.description.trim() : null;
       console.log('create_stream args:', recipientPrincipal, satsPerSec, durationSecs, totalLocked, safeTitle, safeDescription, tagsArray);
       if (selectedTemplate) {
         console.log('Using template with ID:', selectedTemplate.id, 'Type:', typeof selectedTemplate.id);
-        streamId = await backend.create_stream_from_template(
+        result = await backend.create_stream_from_template(
           Number(selectedTemplate.id),
           recipientPrincipal,
           totalLocked
         );
       } else {
-        streamId = await backend.create_stream(
+        result = await backend.create_stream(
           recipientPrincipal,
           satsPerSec,
           durationSecs,
@@ -221,6 +211,13 @@ const CreateStream = () => {
           tagsArray
         );
       }
+      if ('err' in result) {
+        throw new Error(result.err);
+      }
+      if ('quota_exceeded' in result) {
+        throw new Error(describeQuotaError(result.quota_exceeded));
+      }
+      const streamId = result.ok;
 
       setSuccess(true);
       
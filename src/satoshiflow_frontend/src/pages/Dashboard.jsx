@@ -10,8 +10,9 @@ import {
   Activity,
   Users
 } from 'lucide-react';
-import { satoshiflow_backend } from 'declarations/satoshiflow_backend';
 import { useAuth } from '../contexts/AuthContext';
+import { listAllStreamsForUser } from '../utils/listAllStreams';
+import { getAuthenticatedActor } from '../utils/getBackendActor';
 import StreamCard from '../components/StreamCard';
 import StatCard from '../components/StatCard';
 import QuickActionCard from '../components/QuickActionCard';
@@ -58,6 +59,8 @@ function deepBigIntToNumber(obj, seen = new Set()) {
   return out;
 }
 
+const EVENT_POLL_INTERVAL_MS = 3000;
+
 const Dashboard = () => {
   const [recentStreams, setRecentStreams] = useState([]);
   const [stats, setStats] = useState({
@@ -67,7 +70,7 @@ const Dashboard = () => {
     totalStreams: 0,
   });
   const [loading, setLoading] = useState(true);
-  const { user } = useAuth();
+  const { user, walletType } = useAuth();
 
   useEffect(() => {
     if (user) {
@@ -85,9 +88,39 @@ const Dashboard = () => {
     }
   }, [user]);
 
-  const fetchDashboardData = async () => {
+  // Poll the backend event feed so stream changes show up within seconds
+  useEffect(() => {
+    if (!user) return;
+    let since = null;
+    let cancelled = false;
+    // The feed is per caller, so poll as the signed-in user; the actor is reused across polls
+    let backend = null;
+    const poll = async () => {
+      try {
+        backend ??= await getAuthenticatedActor(walletType);
+        const page = await backend.get_events_since(since ?? 0n, []);
+        if (cancelled) return;
+        // The first poll only establishes where the feed currently ends
+        const changed = since !== null && (page.events.length > 0 || page.truncated);
+        since = page.latest_seq;
+        if (changed) {
+          fetchDashboardData(true);
+        }
+      } catch (error) {
+        console.error('Failed to poll stream events:', error);
+      }
+    };
+    poll();
+    const interval = setInterval(poll, EVENT_POLL_INTERVAL_MS);
+    return () => {
+      cancelled = true;
+      clearInterval(interval);
+    };
+  }, [user, walletType]);
+
+  const fetchDashboardData = async (silent = false) => {
     try {
-      setLoading(true);
+      if (!silent) setLoading(true);
       console.log('Fetching dashboard data...');
       
       // Check if user is authenticated
@@ -101,7 +134,7 @@ const Dashboard = () => {
       
       // Fetch recent streams with proper error handling
       console.log('Calling list_streams_for_user...');
-      const userStreams = await satoshiflow_backend.list_streams_for_user(user);
+      const userStreams = await listAllStreamsForUser(user, walletType);
       console.log('Raw user streams received:', userStreams);
       console.log('Number of streams:', userStreams ? userStreams.length : 'undefined');
       
@@ -11,7 +11,8 @@ import {
   DollarSign,
   Play,
   Pause,
-  Square
+  Square,
+  ArrowRightLeft
 } from 'lucide-react';
 import { useNotifications } from '../contexts/NotificationContext';
 import { formatDistanceToNow } from 'date-fns';
@@ -64,6 +65,10 @@ const Notifications = () => {
         return <AlertCircle className="h-5 w-5 text-yellow-500" />;
       case 'ClaimReminder':
         return <Clock className="h-5 w-5 text-purple-500" />;
+      case 'RecipientTransferred':
+        return <ArrowRightLeft className="h-5 w-5 text-indigo-500" />;
+      case 'SenderTransfer':
+        return <ArrowRightLeft className="h-5 w-5 text-teal-500" />;
       default:
         return <Bell className="h-5 w-5 text-gray-500" />;
     }
@@ -85,6 +90,10 @@ const Notifications = () => {
         return 'bg-yellow-50 border-yellow-200';
       case 'ClaimReminder':
         return 'bg-purple-50 border-purple-200';
+      case 'RecipientTransferred':
+        return 'bg-indigo-50 border-indigo-200';
+      case 'SenderTransfer':
+        return 'bg-teal-50 border-teal-200';
       default:
         return 'bg-gray-50 border-gray-200';
     }
@@ -106,6 +115,10 @@ const Notifications = () => {
         return 'Low Balance';
       case 'ClaimReminder':
         return 'Claim Reminder';
+      case 'RecipientTransferred':
+        return 'Recipient Transferred';
+      case 'SenderTransfer':
+        return 'Sender Transfer';
       default:
         return type;
     }
@@ -133,7 +146,9 @@ const Notifications = () => {
     'StreamCancelled',
     'StreamCompleted',
     'LowBalance',
-    'ClaimReminder'
+    'ClaimReminder',
+    'RecipientTransferred',
+    'SenderTransfer'
   ];
 
   if (notifications.length === 0) {
@@ -18,6 +18,7 @@ import {
 import { satoshiflow_backend } from 'declarations/satoshiflow_backend';
 import { useAuth } from '../contexts/AuthContext';
 import { useNotifications } from '../contexts/NotificationContext';
+import { getAuthenticatedActor } from '../utils/getBackendActor';
 import { formatDistanceToNow, format } from 'date-fns';
 
 // Utility: Deeply convert all BigInt fields to Number
@@ -65,7 +66,7 @@ function principalToText(p) {
 const StreamDetails = () => {
   const { id } = useParams();
   const navigate = useNavigate();
-  const { user } = useAuth();
+  const { user, walletType } = useAuth();
   const { fetchNotifications } = useNotifications();
   
   const [stream, setStream] = useState(null);
@@ -76,12 +77,14 @@ const StreamDetails = () => {
 
   useEffect(() => {
     fetchStreamDetails();
-  }, [id]);
+  }, [id, walletType]);
 
   const fetchStreamDetails = async () => {
     try {
       setLoading(true);
-      const streamData = await satoshiflow_backend.get_stream(parseInt(id));
+      // Public streams can be viewed without connecting a wallet
+      const backend = walletType ? await getAuthenticatedActor(walletType) : satoshiflow_backend;
+      const streamData = await backend.get_stream(parseInt(id));
       console.log('Fetched streamData:', streamData);
       // Patch: handle array/tuple return from backend
       let stream = streamData;
@@ -109,7 +112,8 @@ const StreamDetails = () => {
       setError('');
       setSuccess('');
       
-      const result = await satoshiflow_backend.claim_stream(parseInt(id));
+      const backend = await getAuthenticatedActor(walletType);
+      const result = await backend.claim_stream(parseInt(id));
       if (result.ok) {
         setSuccess(`Claimed ${result.ok.amount} sats successfully`);
         fetchStreamDetails(); // Refresh data
@@ -135,7 +139,8 @@ const StreamDetails = () => {
       setError('');
       setSuccess('');
       
-      const result = await satoshiflow_backend.top_up_stream(parseInt(id), parseInt(amount));
+      const backend = await getAuthenticatedActor(walletType);
+      const result = await backend.top_up_stream(parseInt(id), parseInt(amount));
       if (result.ok) {
         setSuccess(`Successfully topped up ${amount} sats!`);
         fetchStreamDetails(); // Refresh data
@@ -160,7 +165,8 @@ const StreamDetails = () => {
       setError('');
       setSuccess('');
       
-      const result = await satoshiflow_backend.cancel_stream(parseInt(id));
+      const backend = await getAuthenticatedActor(walletType);
+      const result = await backend.cancel_stream(parseInt(id));
       if (result.ok) {
         setSuccess(`Stream cancelled. Refund: ${result.ok.refund} sats, Fee: ${result.ok.fee} sats`);
         fetchStreamDetails(); // Refresh data
@@ -183,7 +189,8 @@ const StreamDetails = () => {
       setError('');
       setSuccess('');
       
-      const result = await satoshiflow_backend.pause_stream(parseInt(id));
+      const backend = await getAuthenticatedActor(walletType);
+      const result = await backend.pause_stream(parseInt(id));
       if (result.ok) {
         setSuccess('Stream paused successfully');
         fetchStreamDetails(); // Refresh data
@@ -206,7 +213,8 @@ const StreamDetails = () => {
       setError('');
       setSuccess('');
       
-      const result = await satoshiflow_backend.resume_stream(parseInt(id));
+      const backend = await getAuthenticatedActor(walletType);
+      const result = await backend.resume_stream(parseInt(id));
       if (result.ok) {
         setSuccess('Stream resumed successfully');
         fetchStreamDetails(); // Refresh data
@@ -9,8 +9,8 @@ import {
   User,
   DollarSign
 } from 'lucide-react';
-import { satoshiflow_backend } from 'declarations/satoshiflow_backend';
 import { useAuth } from '../contexts/AuthContext';
+import { listAllStreamsForUser } from '../utils/listAllStreams';
 import StreamCard from '../components/StreamCard';
 import { Link } from 'react-router-dom';
 import { Principal } from '@dfinity/principal';
@@ -92,7 +92,7 @@ const StreamList = () => {
   const [sortBy, setSortBy] = useState('newest');
   const [showFilters, setShowFilters] = useState(false);
   
-  const { user } = useAuth();
+  const { user, walletType } = useAuth();
   // Debug: Log the principal type and value
   useEffect(() => {
     if (user) {
@@ -124,7 +124,7 @@ const StreamList = () => {
       setLoading(true);
       // Debug: Log the type of user before calling backend
       console.log('Calling list_streams_for_user with:', user, typeof user);
-      const userStreams = await satoshiflow_backend.list_streams_for_user(user);
+      const userStreams = await listAllStreamsForUser(user, walletType);
       // Deep convert BigInt fields to Number
       const safeStreams = Array.isArray(userStreams) ? userStreams.map(deepBigIntToNumber) : [];
       console.log('All streams (raw):', userStreams);
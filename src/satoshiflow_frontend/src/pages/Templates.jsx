@@ -13,6 +13,8 @@ import {
 } from 'lucide-react';
 import { satoshiflow_backend } from 'declarations/satoshiflow_backend';
 import { useAuth } from '../contexts/AuthContext';
+import { getAuthenticatedActor } from '../utils/getBackendActor';
+import { describeQuotaError } from '../utils/quotaError';
 
 function principalToText(p) {
   if (!p) return '';
@@ -35,7 +37,7 @@ const Templates = () => {
   });
   const [createLoading, setCreateLoading] = useState(false);
   
-  const { user } = useAuth();
+  const { user, walletType } = useAuth();
 
   useEffect(() => {
     fetchTemplates();
@@ -121,7 +123,8 @@ const Templates = () => {
         sats_per_sec: parseInt(newTemplate.satsPerSec)
       });
       
-      const result = await satoshiflow_backend.create_template(
+      const backend = await getAuthenticatedActor(walletType);
+      const result = await backend.create_template(
         newTemplate.name.trim(),
         newTemplate.description.trim(),
         parseInt(newTemplate.duration) * 60, // Convert minutes to seconds
@@ -141,6 +144,8 @@ const Templates = () => {
       } else if (result && result.err) {
         console.error('Template creation failed:', result.err);
         alert(`Failed to create template: ${result.err}`);
+      } else if (result && result.quota_exceeded) {
+        alert(`Failed to create template: ${describeQuotaError(result.quota_exceeded)}`);
       } else {
         console.log('Unexpected result format, treating as success');
         alert('Template created successfully!');
@@ -3,4 +3,21 @@ import { createActor, canisterId } from '../../../declarations/satoshiflow_backe
 export function getBackendActor(identity) {
   const agentOptions = identity ? { identity } : {};
   return createActor(canisterId, { agentOptions });
-} 
\ No newline at end of file
+}
+
+// Update calls must be signed by the logged-in wallet; the backend rejects anonymous callers
+export async function getAuthenticatedActor(walletType) {
+  let identity = null;
+  if (walletType === 'plug' && window.ic?.plug) {
+    await window.ic.plug.createAgent();
+    identity = window.ic.plug.agent.identity;
+  } else if (walletType === 'ii') {
+    const { AuthClient } = await import('@dfinity/auth-client');
+    const authClient = await AuthClient.create();
+    identity = authClient.getIdentity();
+  }
+  if (!identity) {
+    throw new Error('Please connect a wallet first');
+  }
+  return getBackendActor(identity);
+}
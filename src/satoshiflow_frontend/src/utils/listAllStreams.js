@@ -0,0 +1,23 @@
+import { getAuthenticatedActor } from './getBackendActor';
+
+const PAGE_SIZE = 200;
+
+// list_streams_for_user is paginated; follow the cursor until every stream is loaded.
+// Only the user themselves sees private streams, so the call is made with their identity.
+export async function listAllStreamsForUser(user, walletType) {
+  const backend = await getAuthenticatedActor(walletType);
+  const streams = [];
+  let cursor = [];
+  do {
+    const page = await backend.list_streams_for_user(user, [{
+      sort_by: [],
+      descending: [],
+      role: [],
+      cursor,
+      limit: [PAGE_SIZE],
+    }]);
+    streams.push(...page.streams);
+    cursor = page.next_cursor;
+  } while (cursor.length > 0);
+  return streams;
+}
@@ -0,0 +1,21 @@
+// Human-readable text for the backend's QuotaError variant
+export function describeQuotaError(quota) {
+  if ('CreateRateExceeded' in quota) {
+    const { limit, retry_after_secs } = quota.CreateRateExceeded;
+    const minutes = Math.ceil(Number(retry_after_secs) / 60);
+    return `You can create at most ${limit} streams per hour. Try again in ${minutes} minute(s).`;
+  }
+  if ('TooManyActiveStreams' in quota) {
+    return `You already have the maximum of ${quota.TooManyActiveStreams.limit} active streams.`;
+  }
+  if ('TooManyTemplates' in quota) {
+    return `You can create at most ${quota.TooManyTemplates.limit} templates.`;
+  }
+  if ('TooManyMilestones' in quota) {
+    return `A stream can have at most ${quota.TooManyMilestones.limit} milestones.`;
+  }
+  if ('AmountBelowMinimum' in quota) {
+    return `Streams must lock at least ${quota.AmountBelowMinimum.minimum} sats.`;
+  }
+  return 'Quota exceeded';
+}